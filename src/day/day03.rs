@@ -1,7 +1,17 @@
+use std::io::Read;
 use regex::Regex;
 use crate::day::utils;
 
 pub fn run() {
+    // `cargo run -- 3 -` scans whatever is piped to stdin instead of the input file
+    if std::env::args().nth(2).as_deref() == Some("-") {
+        let (part_one_result, part_two_result) = scan_reader(std::io::stdin().lock())
+            .expect("Failed to read stdin");
+        println!("{}", part_one_result);
+        println!("{}", part_two_result);
+        return;
+    }
+
     let input_string = utils::read_input(3);
     let part_one_result = run_part_one(&input_string);
     println!("{}", part_one_result);
@@ -19,13 +29,199 @@ fn run_part_one(input_string: &str) -> u64 {
 }
 
 fn run_part_two(input_string: &str) -> u64 {
-    let regex = Regex::new(r"(?s)don't\(\).*?do\(\)").unwrap();
-    run_part_one(regex.replace_all(input_string, "").as_ref())
+    // Same scanner as for streams. Cutting out the disabled stretches instead would join what is left around
+    // them into new instructions.
+    let mut scanner = MulScanner::new(true);
+    scanner.feed(input_string.as_bytes());
+    scanner.finish()
+}
+
+// Longest token we ever need to look at is "mul(123,456)"
+const MAX_TOKEN_LEN: usize = 12;
+
+enum Token {
+    Mul(u64),
+    Do,
+    Dont,
+}
+
+enum TokenMatch {
+    Found(Token, usize),
+    NotFound,
+    // Ran out of bytes before being able to decide, need the next chunk
+    Incomplete,
+}
+
+pub struct MulScanner {
+    with_conditionals: bool,
+    enabled: bool,
+    carry: Vec<u8>,
+    sum: u64,
+}
+
+impl MulScanner {
+    pub fn new(with_conditionals: bool) -> MulScanner {
+        MulScanner {
+            with_conditionals,
+            enabled: true,
+            carry: Vec::with_capacity(MAX_TOKEN_LEN),
+            sum: 0,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        if self.carry.is_empty() {
+            let consumed = self.scan(chunk, false);
+            self.carry.extend_from_slice(&chunk[consumed..]);
+        } else {
+            // Only the first few bytes of the new chunk can complete a token started in the carry,
+            // so there is no need to copy the whole chunk over
+            let head_len = chunk.len().min(MAX_TOKEN_LEN);
+            let mut joined = std::mem::take(&mut self.carry);
+            let carry_len = joined.len();
+            joined.extend_from_slice(&chunk[..head_len]);
+
+            let consumed = self.scan_prefix(&joined, carry_len, false);
+            if consumed < carry_len {
+                self.carry = joined;
+                self.carry.drain(..consumed);
+                return;
+            }
+
+            let rest = &chunk[consumed - carry_len..];
+            let consumed = self.scan(rest, false);
+            self.carry.extend_from_slice(&rest[consumed..]);
+        }
+    }
+
+    pub fn finish(mut self) -> u64 {
+        let carry = std::mem::take(&mut self.carry);
+        self.scan(&carry, true);
+        self.sum
+    }
+
+    // Scans tokens starting within the first `limit` bytes of `bytes`. Returns how many bytes were consumed,
+    // which can be less than `limit` if a token starting there needs more data to be decided.
+    fn scan_prefix(&mut self, bytes: &[u8], limit: usize, is_last: bool) -> usize {
+        let mut i = 0;
+        while i < limit {
+            match match_token(&bytes[i..], is_last) {
+                TokenMatch::Found(token, len) => {
+                    self.apply(token);
+                    i += len;
+                }
+                TokenMatch::NotFound => i += 1,
+                TokenMatch::Incomplete => return i,
+            }
+        }
+        i
+    }
+
+    fn scan(&mut self, bytes: &[u8], is_last: bool) -> usize {
+        self.scan_prefix(bytes, bytes.len(), is_last)
+    }
+
+    fn apply(&mut self, token: Token) {
+        match token {
+            Token::Mul(product) => {
+                if self.enabled || !self.with_conditionals {
+                    self.sum += product;
+                }
+            }
+            Token::Do => self.enabled = true,
+            Token::Dont => self.enabled = false,
+        }
+    }
+}
+
+fn match_token(bytes: &[u8], is_last: bool) -> TokenMatch {
+    let result = match bytes.first() {
+        Some(b'm') => match_mul(bytes),
+        Some(b'd') => match (match_literal(bytes, b"don't()"), match_literal(bytes, b"do()")) {
+            (Some(true), _) => Some(TokenMatch::Found(Token::Dont, 7)),
+            (_, Some(true)) => Some(TokenMatch::Found(Token::Do, 4)),
+            (None, _) | (_, None) => None,
+            _ => Some(TokenMatch::NotFound),
+        },
+        _ => Some(TokenMatch::NotFound),
+    };
+
+    match result {
+        Some(token_match) => token_match,
+        None if is_last => TokenMatch::NotFound,
+        None => TokenMatch::Incomplete,
+    }
+}
+
+// None means that the bytes so far are a valid prefix of the literal, but the end of the slice was reached
+fn match_literal(bytes: &[u8], literal: &[u8]) -> Option<bool> {
+    let len = bytes.len().min(literal.len());
+    if bytes[..len] != literal[..len] {
+        return Some(false);
+    }
+    if len < literal.len() {
+        return None;
+    }
+    Some(true)
+}
+
+fn match_mul(bytes: &[u8]) -> Option<TokenMatch> {
+    if !match_literal(bytes, b"mul(")? {
+        return Some(TokenMatch::NotFound);
+    }
+
+    let mut i = 4;
+    let Some((first, len)) = match_number(&bytes[i..])? else { return Some(TokenMatch::NotFound) };
+    i += len;
+    if *bytes.get(i)? != b',' {
+        return Some(TokenMatch::NotFound);
+    }
+    i += 1;
+
+    let Some((second, len)) = match_number(&bytes[i..])? else { return Some(TokenMatch::NotFound) };
+    i += len;
+    if *bytes.get(i)? != b')' {
+        return Some(TokenMatch::NotFound);
+    }
+
+    Some(TokenMatch::Found(Token::Mul(first * second), i + 1))
+}
+
+// Reads 1-3 ascii digits. Inner None means there is no number at the start of the slice.
+fn match_number(bytes: &[u8]) -> Option<Option<(u64, usize)>> {
+    let mut value = 0;
+    for i in 0..3 {
+        let byte = *bytes.get(i)?;
+        if !byte.is_ascii_digit() {
+            return Some(if i == 0 { None } else { Some((value, i)) });
+        }
+        value = value * 10 + (byte - b'0') as u64;
+    }
+    Some(Some((value, 3)))
+}
+
+// Both parts in a single pass, since a stream can't be rewound
+pub fn scan_reader(mut reader: impl Read) -> std::io::Result<(u64, u64)> {
+    let mut part_one_scanner = MulScanner::new(false);
+    let mut part_two_scanner = MulScanner::new(true);
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                part_one_scanner.feed(&buffer[..read]);
+                part_two_scanner.feed(&buffer[..read]);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((part_one_scanner.finish(), part_two_scanner.finish()))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day03::{run_part_one, run_part_two};
+    use crate::day::day03::{run_part_one, run_part_two, scan_reader, MulScanner};
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -55,4 +251,55 @@ mod tests {
     fn test_day2_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(3)), 77055967);
     }
+
+    fn scan_in_chunks(input: &[u8], chunk_size: usize, with_conditionals: bool) -> u64 {
+        let mut scanner = MulScanner::new(with_conditionals);
+        input.chunks(chunk_size).for_each(|chunk| scanner.feed(chunk));
+        scanner.finish()
+    }
+
+    #[test]
+    fn test_scanner_matches_whole_string_on_every_split() {
+        let input = example_input_part_two();
+        for split in 0..=input.len() {
+            let mut scanner = MulScanner::new(true);
+            scanner.feed(&input.as_bytes()[..split]);
+            scanner.feed(&input.as_bytes()[split..]);
+            assert_eq!(scanner.finish(), 48, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_scanner_tiny_chunks_and_invalid_utf8() {
+        let mut input = vec![0xff, 0xfe];
+        input.extend_from_slice(b"mul(2,4)\xc3mu");
+        input.push(0xc3);
+        input.extend_from_slice(b"l(1,1)mul(1234,1)mul(3,4)don't()mul(9,9)do(");
+        input.push(0x80);
+        input.extend_from_slice(b"do()mul(1,5)mul(7,");
+        for chunk_size in 1..=16 {
+            assert_eq!(scan_in_chunks(&input, chunk_size, false), 8 + 12 + 81 + 5);
+            assert_eq!(scan_in_chunks(&input, chunk_size, true), 8 + 12 + 5);
+        }
+    }
+
+    #[test]
+    fn test_part_two_matches_scanner() {
+        // Disabled until the end, and an instruction that only appears if the disabled part is cut out
+        let inputs = ["mul(1,2)don't()mul(3,4)", "mul(1,2)mudon't()mul(5,5)do()l(2,3)mul(4,4)"];
+        let expected = [2, 2 + 16];
+        for (input, expected) in inputs.iter().zip(expected) {
+            assert_eq!(run_part_two(input), expected);
+            for chunk_size in 1..=input.len() {
+                assert_eq!(scan_in_chunks(input.as_bytes(), chunk_size, true), expected, "{} in chunks of {}", input, chunk_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scanner_input() {
+        let input = utils::read_input(3);
+        assert_eq!(scan_reader(input.as_bytes()).unwrap(), (run_part_one(&input), run_part_two(&input)));
+        assert_eq!(scan_in_chunks(input.as_bytes(), 7, true), 77055967);
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+use aho_corasick::AhoCorasick;
 use crate::day::utils;
 
 pub fn run() {
@@ -17,16 +19,8 @@ fn access(matrix: &Matrix, x: usize, y: usize) -> char {
 
 fn run_part_one(input_string: &str) -> u64 {
     let matrix = input_into_matrix(input_string);
-
-    let mut count = 0;
-
-    for y in 0..matrix.len() {
-        for x in 0..matrix[0].len() {
-            count += scan_pos_xmas(&matrix, x, y);
-        }
-    }
-
-    count
+    let options = SearchOptions { allow_overlap: true, ..SearchOptions::default() };
+    find_words(&matrix, &["XMAS"], &options).len() as u64
 }
 
 fn input_into_matrix(input_string: &str) -> Vec<Vec<char>> {
@@ -37,92 +31,335 @@ fn input_into_matrix(input_string: &str) -> Vec<Vec<char>> {
     matrix
 }
 
-fn scan_pos_xmas(matrix: &Matrix, x: usize, y: usize) -> u64 {
-    let mut pos_count = 0;
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+    Up,
+    UpRight,
+}
 
-    // TODO: Consider making it neater by having some Enum dictate the access direction?
-    let char_at_coordinates = access(matrix, x, y);
-    if x + 3 < matrix[0].len() {
-        let char_at_coordinates_2 = access(matrix, x + 1, y);
-        let char_at_coordinates_3 = access(matrix, x + 2, y);
-        let char_at_coordinates_4 = access(matrix, x + 3, y);
-        let result = format!("{}{}{}{}", char_at_coordinates, char_at_coordinates_2, char_at_coordinates_3, char_at_coordinates_4);
-        if result == "XMAS" || result == "SAMX" {
-            pos_count += 1;
+impl Direction {
+    pub const ALL: [Direction; 8] = [Direction::Right, Direction::DownRight, Direction::Down, Direction::DownLeft,
+        Direction::Left, Direction::UpLeft, Direction::Up, Direction::UpRight];
+
+    pub fn delta(&self) -> (isize, isize) {
+        match self {
+            Direction::Right => (1, 0),
+            Direction::DownRight => (1, 1),
+            Direction::Down => (0, 1),
+            Direction::DownLeft => (-1, 1),
+            Direction::Left => (-1, 0),
+            Direction::UpLeft => (-1, -1),
+            Direction::Up => (0, -1),
+            Direction::UpRight => (1, -1),
         }
     }
+}
 
-    if x + 3 < matrix[0].len() && y + 3 < matrix.len() {
-        let char_at_coordinates_2 = access(matrix, x + 1, y + 1);
-        let char_at_coordinates_3 = access(matrix, x + 2, y + 2);
-        let char_at_coordinates_4 = access(matrix, x + 3, y + 3);
-        let result = format!("{}{}{}{}", char_at_coordinates, char_at_coordinates_2, char_at_coordinates_3, char_at_coordinates_4);
-        if result == "XMAS" || result == "SAMX" {
-            pos_count += 1;
-        }
+#[derive(Debug, Default, Clone)]
+pub struct SearchOptions {
+    // Words continue from the opposite edge of the grid when they run off it
+    pub wraparound: bool,
+    // When false, a grid cell can be part of at most one match. Longer words are claimed first.
+    pub allow_overlap: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordMatch {
+    pub word: String,
+    pub x: usize,
+    pub y: usize,
+    pub direction: Direction,
+}
+
+impl WordMatch {
+    // Grid dimensions are needed to resolve cells of matches that wrap around
+    pub fn cells(&self, width: usize, height: usize) -> Vec<(usize, usize)> {
+        let (dx, dy) = self.direction.delta();
+        (0..self.word.chars().count() as isize)
+            .map(|i| (self.x as isize + dx * i, self.y as isize + dy * i))
+            .map(|(x, y)| (x.rem_euclid(width as isize) as usize, y.rem_euclid(height as isize) as usize))
+            .collect()
     }
+}
 
-    if x + 3 < matrix[0].len() && y >= 3 {
-        let char_at_coordinates_2 = access(matrix, x + 1, y - 1);
-        let char_at_coordinates_3 = access(matrix, x + 2, y - 2);
-        let char_at_coordinates_4 = access(matrix, x + 3, y - 3);
-        let result = format!("{}{}{}{}", char_at_coordinates, char_at_coordinates_2, char_at_coordinates_3, char_at_coordinates_4);
-        if result == "XMAS" || result == "SAMX" {
-            pos_count += 1;
+// Every straight line of cells in the grid going in the given direction. With wraparound the lines are the
+// cycles on the torus, otherwise each line starts at a cell whose predecessor is off the grid.
+fn grid_lines(width: usize, height: usize, direction: Direction, wraparound: bool) -> Vec<Vec<(usize, usize)>> {
+    let (dx, dy) = direction.delta();
+    let step = |(x, y): (usize, usize)| -> Option<(usize, usize)> {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if wraparound {
+            Some((nx.rem_euclid(width as isize) as usize, ny.rem_euclid(height as isize) as usize))
+        } else if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+            None
+        } else {
+            Some((nx as usize, ny as usize))
+        }
+    };
+
+    let mut lines = vec![];
+    let mut seen = vec![vec![false; width]; height];
+    for y in 0..height {
+        for x in 0..width {
+            if seen[y][x] {
+                continue;
+            }
+            if !wraparound {
+                let (px, py) = (x as isize - dx, y as isize - dy);
+                if px >= 0 && py >= 0 && px < width as isize && py < height as isize {
+                    continue;
+                }
+            }
+
+            let mut line = vec![];
+            let mut current = Some((x, y));
+            while let Some(pos) = current {
+                if seen[pos.1][pos.0] {
+                    break;
+                }
+                seen[pos.1][pos.0] = true;
+                line.push(pos);
+                current = step(pos);
+            }
+            lines.push(line);
         }
     }
+    lines
+}
 
-    if y + 3 < matrix.len() {
-        let char_at_coordinates_2 = access(matrix, x, y + 1);
-        let char_at_coordinates_3 = access(matrix, x, y + 2);
-        let char_at_coordinates_4 = access(matrix, x, y + 3);
-        let result = format!("{}{}{}{}", char_at_coordinates, char_at_coordinates_2, char_at_coordinates_3, char_at_coordinates_4);
-        if result == "XMAS" || result == "SAMX" {
-            pos_count += 1;
+pub fn find_words(matrix: &Matrix, words: &[&str], options: &SearchOptions) -> Vec<WordMatch> {
+    let height = matrix.len();
+    let width = if height == 0 { 0 } else { matrix[0].len() };
+    let longest_word = words.iter().map(|word| word.chars().count()).max().unwrap_or(0);
+    if width == 0 || longest_word == 0 {
+        return vec![];
+    }
+
+    let automaton = AhoCorasick::new(words).unwrap();
+    let mut matches = vec![];
+
+    for direction in Direction::ALL {
+        for line in grid_lines(width, height, direction, options.wraparound) {
+            // A cycle is searched with its start appended again, so that words crossing the seam are found
+            let extra = if options.wraparound { longest_word - 1 } else { 0 };
+            let positions: Vec<(usize, usize)> = line.iter().cycle().take(line.len() + extra).copied().collect();
+
+            let mut haystack = String::new();
+            let mut char_index_at_byte = vec![];
+            for (i, &(x, y)) in positions.iter().enumerate() {
+                let c = access(matrix, x, y);
+                haystack.push(c);
+                char_index_at_byte.extend(std::iter::repeat_n(i, c.len_utf8()));
+            }
+
+            for found in automaton.find_overlapping_iter(&haystack) {
+                let start = char_index_at_byte[found.start()];
+                // Matches starting in the repeated part were already found at the start of the cycle
+                if start >= line.len() {
+                    continue;
+                }
+                let (x, y) = positions[start];
+                matches.push(WordMatch { word: words[found.pattern().as_usize()].to_string(), x, y, direction });
+            }
         }
     }
 
-    pos_count
+    if !options.allow_overlap {
+        matches.sort_by_key(|word_match| std::cmp::Reverse(word_match.word.chars().count()));
+        let mut used = HashSet::new();
+        matches.retain(|word_match| {
+            let cells = word_match.cells(width, height);
+            if cells.iter().any(|cell| used.contains(cell)) {
+                return false;
+            }
+            used.extend(cells);
+            true
+        });
+    }
+
+    matches.sort_by_key(|word_match| (word_match.y, word_match.x));
+    matches
 }
 
 fn run_part_two(input_string: &str) -> u64 {
     let matrix = input_into_matrix(input_string);
+    let template = Template::parse("\
+M?S
+?A?
+M?S").unwrap();
+    template.count_matches(&matrix, Symmetry { rotations: true, reflections: true }) as u64
+}
 
-    let mut count = 0;
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplateCell {
+    Any,
+    Literal(char),
+    Class { chars: Vec<char>, negated: bool },
+}
 
-    for y in 0..matrix.len() {
-        for x in 0..matrix[0].len() {
-            if scan_pos_xmas_part_two(&matrix, x, y) {
-                count += 1;
-            }
+impl TemplateCell {
+    fn accepts(&self, c: char) -> bool {
+        match self {
+            TemplateCell::Any => true,
+            TemplateCell::Literal(literal) => *literal == c,
+            TemplateCell::Class { chars, negated } => chars.contains(&c) != *negated,
         }
     }
+}
+
+// Which transformations of the template are tried when matching
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Symmetry {
+    pub rotations: bool,
+    pub reflections: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Orientation {
+    // Number of clockwise quarter turns, applied after the reflection
+    pub quarter_turns: u8,
+    // Mirrored left to right
+    pub reflected: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TemplateMatch {
+    // Top left corner of the oriented template in the grid
+    pub x: usize,
+    pub y: usize,
+    pub orientation: Orientation,
+}
 
-    count
+// A 2D pattern written one row per line. Every character is a literal cell, except:
+//  ?        matches any character
+//  [MS]     matches any of the listed characters, [^MS] any character except them
+//  \c       matches c literally, for when c is one of the special characters above
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    cells: Vec<Vec<TemplateCell>>,
 }
 
-fn scan_pos_xmas_part_two(matrix: &Matrix, x: usize, y: usize) -> bool {
-    if x + 2 >= matrix[0].len() || y + 2 >= matrix.len() {
-        return false;
+impl Template {
+    pub fn parse(template_string: &str) -> Result<Template, String> {
+        let mut cells = vec![];
+        for line in template_string.lines() {
+            let mut row = vec![];
+            let mut chars = line.chars();
+            while let Some(c) = chars.next() {
+                row.push(match c {
+                    '?' => TemplateCell::Any,
+                    '\\' => TemplateCell::Literal(chars.next().ok_or("Dangling escape at end of row")?),
+                    '[' => {
+                        let mut class = vec![];
+                        let mut negated = false;
+                        loop {
+                            match chars.next() {
+                                Some(']') => break,
+                                Some('^') if class.is_empty() && !negated => negated = true,
+                                Some('\\') => class.push(chars.next().ok_or("Dangling escape at end of row")?),
+                                Some(c) => class.push(c),
+                                None => return Err(format!("Unclosed character class in row {:?}", line)),
+                            }
+                        }
+                        TemplateCell::Class { chars: class, negated }
+                    }
+                    c => TemplateCell::Literal(c),
+                });
+            }
+            cells.push(row);
+        }
+
+        if cells.is_empty() || cells[0].is_empty() {
+            return Err("Template is empty".to_string());
+        }
+        if cells.iter().any(|row| row.len() != cells[0].len()) {
+            return Err("Template rows have different lengths".to_string());
+        }
+        Ok(Template { cells })
     }
 
-    if access(matrix, x + 1, y + 1) != 'A' {
-        return false;
+    fn width(&self) -> usize {
+        self.cells[0].len()
     }
 
-    if access(matrix, x + 2, y + 2) == access(matrix, x, y) {
-        return false;
+    fn height(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn rotated_clockwise(&self) -> Template {
+        let cells = (0..self.width())
+            .map(|x| (0..self.height()).rev().map(|y| self.cells[y][x].clone()).collect())
+            .collect();
+        Template { cells }
+    }
+
+    fn reflected(&self) -> Template {
+        let cells = self.cells.iter()
+            .map(|row| row.iter().rev().cloned().collect())
+            .collect();
+        Template { cells }
+    }
+
+    // Distinct orientations only, so that a symmetric template doesn't match the same placement several times
+    fn orientations(&self, symmetry: Symmetry) -> Vec<(Orientation, Template)> {
+        let reflections: &[bool] = if symmetry.reflections { &[false, true] } else { &[false] };
+        let quarter_turns = if symmetry.rotations { 4 } else { 1 };
+
+        let mut orientations: Vec<(Orientation, Template)> = vec![];
+        for &reflected in reflections {
+            let mut template = if reflected { self.reflected() } else { self.clone() };
+            for turns in 0..quarter_turns {
+                if !orientations.iter().any(|(_, existing)| *existing == template) {
+                    orientations.push((Orientation { quarter_turns: turns, reflected }, template.clone()));
+                }
+                template = template.rotated_clockwise();
+            }
+        }
+        orientations
+    }
+
+    fn matches_at(&self, matrix: &Matrix, x: usize, y: usize) -> bool {
+        self.cells.iter().enumerate().all(|(dy, row)| {
+            row.iter().enumerate().all(|(dx, cell)| cell.accepts(access(matrix, x + dx, y + dy)))
+        })
+    }
+
+    pub fn find_matches(&self, matrix: &Matrix, symmetry: Symmetry) -> Vec<TemplateMatch> {
+        let height = matrix.len();
+        let width = if height == 0 { 0 } else { matrix[0].len() };
+
+        let mut matches = vec![];
+        for (orientation, template) in self.orientations(symmetry) {
+            if template.width() > width || template.height() > height {
+                continue;
+            }
+            for y in 0..=height - template.height() {
+                for x in 0..=width - template.width() {
+                    if template.matches_at(matrix, x, y) {
+                        matches.push(TemplateMatch { x, y, orientation });
+                    }
+                }
+            }
+        }
+
+        matches.sort_by_key(|template_match| (template_match.y, template_match.x));
+        matches
     }
 
-    let mut chars = [access(matrix, x, y), access(matrix, x + 2, y), access(matrix, x + 2, y + 2),
-        access(matrix, x, y + 2)];
-    chars.sort();
-    chars == ['M', 'M', 'S', 'S']
+    pub fn count_matches(&self, matrix: &Matrix, symmetry: Symmetry) -> usize {
+        self.find_matches(matrix, symmetry).len()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day04::{run_part_one, run_part_two};
+    use crate::day::day04::{find_words, run_part_one, run_part_two, Direction, Orientation, SearchOptions, Symmetry, Template, TemplateMatch};
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -158,4 +395,94 @@ MXMXAXMASX")
     fn test_day2_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(4)), 1835);
     }
+
+    fn grid(input: &str) -> Vec<Vec<char>> {
+        input.lines().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_find_words_dictionary() {
+        let matrix = grid("\
+CATD
+OXXO
+WXXG
+XXXX");
+        let words = ["CAT", "DOG", "COW", "GOD", "ODD"];
+        let overlapping = SearchOptions { allow_overlap: true, ..SearchOptions::default() };
+        let found: Vec<_> = find_words(&matrix, &words, &overlapping).into_iter()
+            .map(|m| (m.word, m.x, m.y, m.direction))
+            .collect();
+        assert_eq!(found, vec![
+            ("CAT".to_string(), 0, 0, Direction::Right),
+            ("COW".to_string(), 0, 0, Direction::Down),
+            ("DOG".to_string(), 3, 0, Direction::Down),
+            ("GOD".to_string(), 3, 2, Direction::Up),
+        ]);
+
+        let exclusive: Vec<_> = find_words(&matrix, &words, &SearchOptions::default()).into_iter()
+            .map(|m| m.word)
+            .collect();
+        assert_eq!(exclusive, vec!["CAT", "DOG"]);
+    }
+
+    #[test]
+    fn test_find_words_overlap_and_wraparound() {
+        let matrix = grid("\
+ATXC
+XXXX");
+        let overlapping = SearchOptions { allow_overlap: true, wraparound: true };
+        let matches = find_words(&matrix, &["CAT", "AT"], &overlapping);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].word, "AT");
+        assert_eq!((matches[1].word.as_str(), matches[1].x, matches[1].y), ("CAT", 3, 0));
+        assert_eq!(matches[1].cells(4, 2), vec![(3, 0), (0, 0), (1, 0)]);
+
+        let exclusive = SearchOptions { allow_overlap: false, wraparound: true };
+        assert_eq!(find_words(&matrix, &["CAT", "AT"], &exclusive).len(), 1);
+        assert!(find_words(&matrix, &["CAT"], &SearchOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_template_orientations() {
+        let matrix = grid("\
+XLXX
+XLXX
+XLLX
+XXXX");
+        let template = Template::parse("L?\nL?\nLL").unwrap();
+        assert_eq!(template.count_matches(&matrix, Symmetry::default()), 1);
+
+        let rotated = grid("\
+XXXX
+LLLX
+LXXX");
+        assert_eq!(template.count_matches(&rotated, Symmetry::default()), 0);
+        assert_eq!(template.find_matches(&rotated, Symmetry { rotations: true, reflections: false }), vec![
+            TemplateMatch { x: 0, y: 1, orientation: Orientation { quarter_turns: 1, reflected: false } },
+        ]);
+
+        let mirrored = grid("\
+XXL
+XXL
+XLL");
+        assert_eq!(template.count_matches(&mirrored, Symmetry { rotations: true, reflections: false }), 0);
+        assert_eq!(template.find_matches(&mirrored, Symmetry { rotations: true, reflections: true }), vec![
+            TemplateMatch { x: 1, y: 0, orientation: Orientation { quarter_turns: 0, reflected: true } },
+        ]);
+    }
+
+    #[test]
+    fn test_template_classes() {
+        let template = Template::parse("[MS]?[^X]\n?\\??\n[MS]??").unwrap();
+        assert_eq!(template.count_matches(&grid("MAS\nA?A\nSAM"), Symmetry::default()), 1);
+        assert_eq!(template.count_matches(&grid("MAX\nA?A\nSAM"), Symmetry::default()), 0);
+        assert_eq!(template.count_matches(&grid("MAS\nAAA\nSAM"), Symmetry::default()), 0);
+
+        // Fully symmetric template only matches once per placement
+        let dot = Template::parse("A").unwrap();
+        assert_eq!(dot.count_matches(&grid("AB\nBA"), Symmetry { rotations: true, reflections: true }), 2);
+
+        assert!(Template::parse("[AB\nCD").is_err());
+        assert!(Template::parse("AB\nC").is_err());
+    }
 }
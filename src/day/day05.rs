@@ -1,9 +1,21 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use itertools::Itertools;
+use pathfinding::directed::strongly_connected_components::strongly_connected_components;
 use crate::day::utils;
 
 pub fn run() {
     let input_string = utils::read_input(5);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+    for (update, cycle) in repair_updates(&parse_input(&input_string)).1 {
+        println!("Skipped update {}, {}", update.iter().join(","), cycle);
+    }
+
+    // `cargo run -- 5 analyse` also prints a report on the ordering rules
+    if std::env::args().nth(2).as_deref() == Some("analyse") {
+        print_rule_analysis(&input_string);
+    }
 }
 
 
@@ -15,40 +27,16 @@ struct Manual {
 fn run_part_one(input_string: &str) -> usize {
     let manual = parse_input(input_string);
 
-    // for each element
     manual.updates.iter()
-        .filter(|update| find_fault_index(&update, &manual.order) == -1)
+        .filter(|update| is_correctly_ordered(update, &manual.order))
         // Assumes that all manual updates have odd number of items
         .map(|update| update.get(update.len() / 2).unwrap())
         .sum()
 }
 
-fn find_fault_index(update: &Vec<usize>, orderings: &Vec<(usize, usize)>) -> isize {
-    for i in 0..update.len() {
-        let elem = update.get(i).unwrap();
-
-        let (left, right) = update.split_at(i);
-
-        // for every element in left there must NOT be an ordering rule where elem|left_elem
-        // for every element in right there must NOT be an ordering rule where right_elem|elem
-        for left_elem in left {
-            for (before, after) in orderings {
-                if before == elem && after == left_elem {
-                    return i as isize;
-                }
-            }
-        }
-
-        for right_elem in right {
-            for (before, after) in orderings {
-                if before == right_elem && after == elem {
-                    return i as isize;
-                }
-            }
-        }
-    }
-
-    -1
+// The sort keeps pages in their original order wherever the rules allow it, so a correct update comes back unchanged
+fn is_correctly_ordered(update: &[usize], orderings: &[(usize, usize)]) -> bool {
+    fix_update_ordering(update, orderings).is_ok_and(|ordered| ordered == update)
 }
 
 fn parse_input(input_string: &str) -> Manual {
@@ -81,63 +69,285 @@ fn parse_input(input_string: &str) -> Manual {
 }
 
 fn run_part_two(input_string: &str) -> usize {
-    let manual = parse_input(input_string);
-
-    manual.updates.iter()
-        .filter(|update| find_fault_index(&update, &manual.order) != -1)
-        .map(|update| fix_update_ordering(&update, &manual.order))
+    repair_updates(&parse_input(input_string)).0.iter()
         .map(|update| {
             *update.get(update.len() / 2).unwrap()
         })
         .sum()
 }
 
-// turbo slow brute force, almost as bad as randomizing the list each iteration, then checking if order is correct
-fn fix_update_ordering(update: &Vec<usize>, orderings: &Vec<(usize, usize)>) -> Vec<usize> {
-    let mut new_vec = update.clone();
-
-    let mut i = find_fault_index(&new_vec, orderings);
-    while i != -1 {
-        let faulty_elem = new_vec[i as usize];
-
-        for left_index in 0..i as usize {
-            let left_elem = new_vec[left_index];
-            for &(before, after) in orderings {
-                if before == faulty_elem && after == left_elem {
-                    let index_a = i as usize;
-
-                    // oh no
-                    let mut cloned_vec = new_vec.clone();
-                    cloned_vec.swap(index_a, left_index);
-                    new_vec = cloned_vec;
-                    break;
+// An update no order satisfies all the rules of, so there is nothing to fix it to
+type SkippedUpdate<'a> = (&'a Vec<usize>, RuleCycle);
+
+// The badly ordered updates put in order, and the ones that can't be
+fn repair_updates(manual: &Manual) -> (Vec<Vec<usize>>, Vec<SkippedUpdate<'_>>) {
+    let mut repaired = vec![];
+    let mut skipped = vec![];
+    for update in &manual.updates {
+        match fix_update_ordering(update, &manual.order) {
+            Ok(ordered) if ordered == *update => {}
+            Ok(ordered) => repaired.push(ordered),
+            Err(cycle) => skipped.push((update, cycle)),
+        }
+    }
+    (repaired, skipped)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCycle {
+    // Each page must come before the next one, and the last one before the first
+    pub pages: Vec<usize>,
+}
+
+impl fmt::Display for RuleCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pages = self.pages.iter().chain(self.pages.first()).join("|");
+        write!(f, "ordering rules form a cycle: {}", pages)
+    }
+}
+
+// Topological sort over only the rules that mention two pages of the update. Pages that aren't constrained
+// relative to each other keep their original order.
+fn fix_update_ordering(update: &[usize], orderings: &[(usize, usize)]) -> Result<Vec<usize>, RuleCycle> {
+    let index_of: HashMap<usize, usize> = update.iter().enumerate().map(|(i, &page)| (page, i)).collect();
+
+    let mut successors = vec![HashSet::new(); update.len()];
+    for (before, after) in orderings {
+        if let (Some(&before), Some(&after)) = (index_of.get(before), index_of.get(after)) {
+            successors[before].insert(after);
+        }
+    }
+
+    let mut in_degree = vec![0; update.len()];
+    successors.iter().flatten().for_each(|&after| in_degree[after] += 1);
+
+    let mut available: BTreeSet<usize> = (0..update.len()).filter(|&i| in_degree[i] == 0).collect();
+    let mut ordered = Vec::with_capacity(update.len());
+    while let Some(i) = available.pop_first() {
+        ordered.push(update[i]);
+        for &after in &successors[i] {
+            in_degree[after] -= 1;
+            if in_degree[after] == 0 {
+                available.insert(after);
+            }
+        }
+    }
+
+    if ordered.len() < update.len() {
+        let remaining: Vec<usize> = (0..update.len()).filter(|&i| in_degree[i] > 0).collect();
+        let cycle = shortest_cycle(&remaining, &successors);
+        return Err(RuleCycle { pages: cycle.into_iter().map(|i| update[i]).collect() });
+    }
+
+    Ok(ordered)
+}
+
+// BFS from every node back to itself, keeping the shortest loop found. Updates are small enough for this.
+fn shortest_cycle(nodes: &[usize], successors: &[HashSet<usize>]) -> Vec<usize> {
+    let mut best: Option<Vec<usize>> = None;
+
+    for &start in nodes {
+        let mut parent = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(current) = queue.pop_front() {
+            if successors[current].contains(&start) {
+                let mut cycle = vec![current];
+                while let Some(&previous) = parent.get(cycle.last().unwrap()) {
+                    cycle.push(previous);
+                }
+                cycle.reverse();
+                if best.as_ref().is_none_or(|best| cycle.len() < best.len()) {
+                    best = Some(cycle);
+                }
+                break;
+            }
+            for &next in successors[current].iter().sorted() {
+                if next != start && !parent.contains_key(&next) {
+                    parent.insert(next, current);
+                    queue.push_back(next);
                 }
             }
         }
+    }
+
+    best.expect("Kahn's algorithm only gets stuck on a cycle")
+}
+
+pub struct RuleGraph {
+    pages: Vec<usize>,
+    rules: Vec<(usize, usize)>,
+    successors: HashMap<usize, HashSet<usize>>,
+}
+
+impl RuleGraph {
+    pub fn new(orderings: &[(usize, usize)]) -> RuleGraph {
+        let rules: Vec<(usize, usize)> = orderings.iter().copied().unique().collect();
+        let pages = rules.iter().flat_map(|&(before, after)| [before, after]).unique().sorted().collect();
+
+        let mut successors: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for &(before, after) in &rules {
+            successors.entry(before).or_default().insert(after);
+        }
+
+        RuleGraph { pages, rules, successors }
+    }
+
+    fn successors_of(&self, page: usize) -> impl Iterator<Item = usize> + '_ {
+        self.successors.get(&page).into_iter().flatten().copied()
+    }
+
+    // Whether `to` can be reached from `from` without using the direct rule from|to
+    fn reachable_indirectly(&self, from: usize, to: usize) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<usize> = self.successors_of(from).filter(|&page| page != to).collect();
+        while let Some(page) = stack.pop() {
+            if page == to {
+                return true;
+            }
+            if seen.insert(page) {
+                stack.extend(self.successors_of(page));
+            }
+        }
+        false
+    }
+
+    // Redundancy is only well defined when the rules are acyclic, as inside a cycle every rule is implied
+    // by going around the rest of it
+    pub fn redundant_rules(&self) -> Result<Vec<(usize, usize)>, RuleCycle> {
+        fix_update_ordering(&self.pages, &self.rules)?;
+        Ok(self.rules.iter()
+            .filter(|&&(before, after)| self.reachable_indirectly(before, after))
+            .copied()
+            .collect())
+    }
+
+    pub fn transitive_reduction(&self) -> Result<Vec<(usize, usize)>, RuleCycle> {
+        let redundant: HashSet<(usize, usize)> = self.redundant_rules()?.into_iter().collect();
+        Ok(self.rules.iter().filter(|rule| !redundant.contains(rule)).copied().collect())
+    }
 
-        for right_index in (i as usize + 1)..new_vec.len() {
-            let right_elem = new_vec[right_index];
-            for &(before, after) in orderings {
-                if before == right_elem && after == faulty_elem {
-                    let index_a = i as usize;
-                    let mut cloned_vec = new_vec.clone();
+    pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        strongly_connected_components(&self.pages, |&page| self.successors_of(page).collect::<Vec<_>>())
+            .into_iter()
+            .map(|component| component.into_iter().sorted().collect())
+            .collect()
+    }
 
-                    cloned_vec.swap(index_a, right_index);
-                    new_vec = cloned_vec;
-                    break;
+    // Every rule before|after where `after` comes earlier in the update than `before`
+    pub fn violated_rules(&self, update: &[usize]) -> Vec<(usize, usize)> {
+        let index_of: HashMap<usize, usize> = update.iter().enumerate().map(|(i, &page)| (page, i)).collect();
+        self.rules.iter()
+            .filter(|(before, after)| match (index_of.get(before), index_of.get(after)) {
+                (Some(before_index), Some(after_index)) => after_index < before_index,
+                _ => false,
+            })
+            .copied()
+            .collect()
+    }
+
+    // Only the rules between the given pages. The full rule set may well be cyclic while every update on its own isn't.
+    pub fn induced(&self, pages: &[usize]) -> RuleGraph {
+        let pages: HashSet<usize> = pages.iter().copied().collect();
+        let rules = self.rules.iter()
+            .filter(|(before, after)| pages.contains(before) && pages.contains(after))
+            .copied()
+            .collect_vec();
+        RuleGraph::new(&rules)
+    }
+
+    // Number of orderings of the update's pages that break none of the rules. Counted with a DP over the sets of
+    // pages that can be placed first, which only grows with how many pages are unordered relative to each other,
+    // not with the length of the update. None if that is too many to go through or the count doesn't fit.
+    pub fn count_valid_orderings(&self, update: &[usize]) -> Option<u64> {
+        if update.len() > 128 {
+            return None;
+        }
+
+        // Bitmask of the pages that have to be placed before each page
+        let mut required_before = vec![0u128; update.len()];
+        for (i, &before) in update.iter().enumerate() {
+            for (j, &after) in update.iter().enumerate() {
+                if self.successors.get(&before).is_some_and(|successors| successors.contains(&after)) {
+                    required_before[j] |= 1 << i;
                 }
             }
         }
 
-        i = find_fault_index(&new_vec, orderings);
+        // One layer per number of placed pages
+        let mut ways: HashMap<u128, u64> = HashMap::from([(0, 1)]);
+        for _ in 0..update.len() {
+            let mut next_ways: HashMap<u128, u64> = HashMap::new();
+            for (&placed, &count) in &ways {
+                for (next, &required) in required_before.iter().enumerate() {
+                    if placed & (1 << next) == 0 && required & placed == required {
+                        let entry = next_ways.entry(placed | (1 << next)).or_default();
+                        *entry = entry.checked_add(count)?;
+                    }
+                }
+                if next_ways.len() > MAX_ORDERING_STATES {
+                    return None;
+                }
+            }
+            ways = next_ways;
+        }
+
+        Some(ways.values().sum())
+    }
+}
+
+const MAX_ORDERING_STATES: usize = 1 << 18;
+
+fn print_rule_analysis(input_string: &str) {
+    let manual = parse_input(input_string);
+    let graph = RuleGraph::new(&manual.order);
+
+    println!("{} rules over {} pages", graph.rules.len(), graph.pages.len());
+    let components = graph.strongly_connected_components();
+    let cyclic_components = components.iter().filter(|component| component.len() > 1).collect_vec();
+    println!("{} strongly connected components, {} of them cyclic", components.len(), cyclic_components.len());
+    for component in cyclic_components {
+        println!("  cycle through {}", component.iter().join(","));
+    }
+
+    match graph.transitive_reduction() {
+        Ok(reduction) => println!("{} rules remain after transitive reduction", reduction.len()),
+        Err(cycle) => {
+            println!("no transitive reduction of all rules, {}", cycle);
+            // A rule can still be left out if within each update it applies to, it follows from the others
+            let mut needed = HashSet::new();
+            let mut applicable = HashSet::new();
+            let mut cyclic_updates = 0;
+            for update in &manual.updates {
+                let induced = graph.induced(update);
+                match induced.transitive_reduction() {
+                    Ok(reduction) => needed.extend(reduction),
+                    Err(_) => {
+                        needed.extend(induced.rules.iter().copied());
+                        cyclic_updates += 1;
+                    }
+                }
+                applicable.extend(induced.rules);
+            }
+            println!("{} of the {} rules applying to some update are implied by the others within every update, \
+                      {} updates have cyclic rules", applicable.len() - needed.len(), applicable.len(), cyclic_updates);
+        }
     }
 
-    new_vec
+    for update in &manual.updates {
+        let violations = graph.violated_rules(update);
+        if violations.is_empty() {
+            continue;
+        }
+        let orderings = graph.count_valid_orderings(update)
+            .map_or("too many to count".to_string(), |count| count.to_string());
+        println!("{} breaks {} (valid orderings: {})", update.iter().join(","),
+                 violations.iter().map(|(before, after)| format!("{}|{}", before, after)).join(" "), orderings);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day05::{run_part_one, run_part_two};
+    use crate::day::day05::{fix_update_ordering, parse_input, repair_updates, run_part_one, run_part_two, RuleCycle, RuleGraph};
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -191,4 +401,91 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(5)), 6142);
     }
+
+    #[test]
+    fn test_part_two_skips_cyclic_updates() {
+        let input = "1|2\n2|3\n3|1\n4|5\n\n1,2,3\n5,4,6\n4,5,6";
+        assert_eq!(run_part_one(input), 5);
+        assert_eq!(run_part_two(input), 5);
+
+        let manual = parse_input(input);
+        let (repaired, skipped) = repair_updates(&manual);
+        assert_eq!(repaired, vec![vec![4, 5, 6]]);
+        assert_eq!(skipped, vec![(&vec![1, 2, 3], RuleCycle { pages: vec![1, 2, 3] })]);
+    }
+
+    #[test]
+    fn test_fix_update_ordering_non_transitive_rules() {
+        // No rule between 1 and 3 directly, only through 2
+        let orderings = vec![(1, 2), (2, 3)];
+        assert_eq!(fix_update_ordering(&[3, 2, 1], &orderings), Ok(vec![1, 2, 3]));
+        assert_eq!(fix_update_ordering(&[3, 9, 1], &orderings), Ok(vec![3, 9, 1]));
+    }
+
+    #[test]
+    fn test_fix_update_ordering_reports_minimal_cycle() {
+        let orderings = vec![(1, 2), (2, 3), (3, 4), (4, 1), (3, 5), (5, 3), (6, 1)];
+        let cycle = fix_update_ordering(&[6, 4, 3, 2, 1, 5], &orderings).unwrap_err();
+        assert_eq!(cycle, RuleCycle { pages: vec![3, 5] });
+        assert_eq!(cycle.to_string(), "ordering rules form a cycle: 3|5|3");
+
+        let cycle = fix_update_ordering(&[1, 2, 3, 4], &orderings).unwrap_err();
+        assert_eq!(cycle.pages.len(), 4);
+    }
+
+    #[test]
+    fn test_rule_graph_reduction() {
+        let graph = RuleGraph::new(&[(1, 2), (2, 3), (1, 3), (3, 4), (1, 4), (5, 4)]);
+        assert_eq!(graph.redundant_rules(), Ok(vec![(1, 3), (1, 4)]));
+        assert_eq!(graph.transitive_reduction(), Ok(vec![(1, 2), (2, 3), (3, 4), (5, 4)]));
+        assert_eq!(graph.count_valid_orderings(&[1, 2, 3, 4, 5]), Some(4));
+        assert_eq!(graph.count_valid_orderings(&[2, 5, 7]), Some(6));
+
+        let cyclic = RuleGraph::new(&[(1, 2), (2, 3), (3, 1), (3, 4)]);
+        assert_eq!(cyclic.transitive_reduction(), Err(RuleCycle { pages: vec![1, 2, 3] }));
+        assert_eq!(cyclic.count_valid_orderings(&[1, 2, 3]), Some(0));
+        let mut components = cyclic.strongly_connected_components();
+        components.sort();
+        assert_eq!(components, vec![vec![1, 2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn test_rule_graph_induced_by_update() {
+        // Cyclic as a whole, but not within updates that leave out one of the pages on the cycle
+        let graph = RuleGraph::new(&[(1, 2), (2, 3), (1, 3), (3, 1), (3, 4), (2, 4)]);
+        assert!(graph.transitive_reduction().is_err());
+        let induced = graph.induced(&[1, 2, 3]);
+        assert!(induced.transitive_reduction().is_err());
+        let induced = graph.induced(&[2, 3, 4, 7]);
+        assert_eq!(induced.redundant_rules(), Ok(vec![(2, 4)]));
+        assert_eq!(induced.transitive_reduction(), Ok(vec![(2, 3), (3, 4)]));
+    }
+
+    #[test]
+    fn test_count_valid_orderings_long_updates() {
+        // Two chains of 15 pages each, which can be interleaved in 30 choose 15 ways
+        let chain = |start: usize| (start..start + 14).map(|page| (page, page + 1)).collect::<Vec<_>>();
+        let graph = RuleGraph::new(&[chain(0), chain(100)].concat());
+        let update = (0..15).chain(100..115).collect::<Vec<_>>();
+        assert_eq!(graph.count_valid_orderings(&update), Some(155117520));
+        assert_eq!(graph.count_valid_orderings(&(0..15).rev().collect::<Vec<_>>()), Some(1));
+
+        // Nothing ordered, far too many subsets to go through
+        assert_eq!(graph.count_valid_orderings(&(200..240).collect::<Vec<_>>()), None);
+
+        let graph = RuleGraph::new(&parse_input(&utils::read_input(5)).order);
+        let update = parse_input(&utils::read_input(5)).updates.into_iter().max_by_key(|update| update.len()).unwrap();
+        assert!(update.len() > 20);
+        assert_eq!(graph.count_valid_orderings(&update), Some(1));
+    }
+
+    #[test]
+    fn test_rule_graph_violations() {
+        let manual = parse_input(&example_input());
+        let graph = RuleGraph::new(&manual.order);
+        assert!(graph.violated_rules(&[75, 47, 61, 53, 29]).is_empty());
+        assert_eq!(graph.violated_rules(&[61, 13, 29]), vec![(29, 13)]);
+        assert_eq!(graph.violated_rules(&[97, 13, 75, 29, 47]), vec![(29, 13), (47, 13), (47, 29), (75, 13)]);
+        assert_eq!(graph.count_valid_orderings(&[97, 13, 75, 29, 47]), Some(1));
+    }
 }
@@ -1,198 +1,636 @@
-use std::collections::{HashSet};
-use indexmap::IndexSet;
+use std::collections::{HashMap, HashSet};
 use itertools::Itertools;
 use crate::day::utils;
-use crate::day::utils::{access, input_into_matrix, Matrix};
+use crate::day::utils::{access, input_into_matrix};
 
 pub fn run() {
     let input_string = utils::read_input(6);
+
+    // Guard behaviour can be changed from the command line, e.g. `cargo run -- 6 alternate block`. Adding
+    // `report` prints the patrol instead, and `report=x,y` the loop caused by an obstruction at x,y.
+    let mut config_args = std::env::args().skip(2).collect_vec();
+    let report_arg = config_args.iter().position(|arg| arg.starts_with("report"))
+        .map(|index| config_args.remove(index));
+    if let Some(report_arg) = report_arg {
+        print_patrol_report(&input_string, &parse_config(&config_args), &report_arg);
+        return;
+    }
+    if !config_args.is_empty() {
+        let config = parse_config(&config_args);
+        let mut lab = Lab::parse(&input_string);
+        println!("{}", lab.visited_cells(&config).len());
+        println!("{}", lab.loop_obstacles(&config).len());
+        return;
+    }
+
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
 }
 
-fn get_movement_delta(current_direction: char) -> (i8, i8) {
-    match current_direction {
-        '^' => (0, -1),
-        '>' => (1, 0),
-        'v' => (0, 1),
-        '<' => (-1, 0),
-        _ => panic!("Not allowed!")
+fn parse_config(args: &[String]) -> GuardConfig {
+    let mut config = GuardConfig::default();
+    for arg in args {
+        match arg.as_str() {
+            "right" => config.turn_policy = TurnPolicy::Right,
+            "left" => config.turn_policy = TurnPolicy::Left,
+            "alternate" => config.turn_policy = TurnPolicy::Alternate,
+            "reverse" => config.turn_policy = TurnPolicy::Reverse,
+            "pass" => config.collisions = Collisions::PassThrough,
+            "block" => config.collisions = Collisions::Block,
+            _ => panic!("Unknown guard option {}", arg),
+        }
     }
+    config
 }
 
-fn turn(current_direction: char) -> char {
-    match current_direction {
-        '^' => '>',
-        '>' => 'v',
-        'v' => '<',
-        '<' => '^',
-        _ => panic!("Not allowed!")
+fn print_patrol_report(input_string: &str, config: &GuardConfig, report_arg: &str) {
+    let mut lab = Lab::parse(input_string);
+    if !lab.is_simple() {
+        println!("Patrol reports are only available for a single guard without portals, the map has {} guards and {} portals",
+                 lab.guards.len(), lab.simulator.portals.len() / 2);
+        return;
+    }
+
+    let obstruction = match report_arg.strip_prefix("report=").map(|position| lab.parse_obstruction(position)) {
+        None => None,
+        Some(Ok(obstruction)) => Some(obstruction),
+        Some(Err(error)) => {
+            println!("{}", error);
+            return;
+        }
+    };
+
+    match obstruction {
+        None => {
+            let report = lab.patrol_report(config).unwrap();
+            println!("{}", lab.render_report(&report, None));
+            println!("{} states, {} turns, {:?}", report.states.len(), report.turn_points().len(), report.end);
+            for (obstruction, report) in lab.loop_reports(config).unwrap() {
+                println!("obstruction at {:?} loops after {} states, {:?}", obstruction, report.states.len(), report.end);
+            }
+        }
+        Some(obstruction) => {
+            lab.simulator.insert_obstacle(obstruction);
+            let report = lab.patrol_report(config).unwrap();
+            println!("{}", lab.render_report(&report, Some(obstruction)));
+            println!("{:?}", report.end);
+        }
     }
 }
 
-fn run_part_one(input_string: &str) -> usize {
-    let mut matrix: Matrix = input_into_matrix(input_string);
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Heading {
+    Up,
+    Right,
+    Down,
+    Left,
+}
 
-    let mut visited_coordinates = HashSet::new();
+impl Heading {
+    pub const ALL: [Heading; 4] = [Heading::Up, Heading::Right, Heading::Down, Heading::Left];
 
-    let mut current_pos = (0, 0);
-    for y in 0..matrix.len() {
-        for x in 0..matrix[y].len() {
-            // Assume starting pos is up
-            if matrix[y][x] == '^' {
-                current_pos = (x, y);
-                visited_coordinates.insert(current_pos);
-            }
+    pub fn from_char(c: char) -> Option<Heading> {
+        match c {
+            '^' => Some(Heading::Up),
+            '>' => Some(Heading::Right),
+            'v' => Some(Heading::Down),
+            '<' => Some(Heading::Left),
+            _ => None,
         }
     }
 
-    loop {
-        let delta = get_movement_delta(access(&matrix, current_pos.0, current_pos.1));
-        let new_pos = (current_pos.0 as isize + delta.0 as isize, current_pos.1 as isize + delta.1 as isize);
+    fn delta(&self) -> (isize, isize) {
+        match self {
+            Heading::Up => (0, -1),
+            Heading::Right => (1, 0),
+            Heading::Down => (0, 1),
+            Heading::Left => (-1, 0),
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
 
-        if new_pos.1 >= matrix[0].len() as isize || new_pos.0 >= matrix.len() as isize || new_pos.0 < 0 || new_pos.1 < 0 {
-            break;
+    pub fn turn_right(&self) -> Heading {
+        Heading::ALL[(self.index() + 1) % 4]
+    }
+
+    pub fn turn_left(&self) -> Heading {
+        Heading::ALL[(self.index() + 3) % 4]
+    }
+
+    pub fn reverse(&self) -> Heading {
+        Heading::ALL[(self.index() + 2) % 4]
+    }
+}
+
+// What a guard does when it walks into an obstacle
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TurnPolicy {
+    #[default]
+    Right,
+    Left,
+    // Right on the first obstacle, left on the second and so on
+    Alternate,
+    Reverse,
+}
+
+impl TurnPolicy {
+    fn turn(&self, heading: Heading, turns_taken: usize) -> Heading {
+        match self {
+            TurnPolicy::Right => heading.turn_right(),
+            TurnPolicy::Left => heading.turn_left(),
+            TurnPolicy::Alternate if turns_taken.is_multiple_of(2) => heading.turn_right(),
+            TurnPolicy::Alternate => heading.turn_left(),
+            TurnPolicy::Reverse => heading.reverse(),
         }
+    }
 
-        match access(&matrix, new_pos.0 as usize, new_pos.1 as usize) {
-            '#' => {
-                let new_direction = turn(access(&matrix, current_pos.0, current_pos.1));
-                matrix[current_pos.1][current_pos.0] = new_direction;
-            },
-            '.' => {
-                let existing_direction = access(&matrix, current_pos.0, current_pos.1);
-                matrix[current_pos.1][current_pos.0] = '.';
-                matrix[new_pos.1 as usize][new_pos.0 as usize] = existing_direction;
-                visited_coordinates.insert((new_pos.0 as usize, new_pos.1 as usize));
-                current_pos = (new_pos.0 as usize, new_pos.1 as usize);
-            }
-            _ => {
-                panic!("Unexpected char");
-            }
+    // The part of the turn count that the next turn depends on, so it has to be part of any loop check
+    fn phase(&self, turns_taken: usize) -> usize {
+        match self {
+            TurnPolicy::Alternate => turns_taken % 2,
+            _ => 0,
         }
     }
+}
 
-    visited_coordinates.len()
+// How guards treat each other when moving in lockstep
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Collisions {
+    // Guards walk through each other
+    #[default]
+    PassThrough,
+    // A guard treats a cell occupied by another guard like an obstacle. Guards move one after another in
+    // the order they appear on the map, so two guards can never swap places or end up on the same cell.
+    Block,
 }
 
-fn run_part_two(input_string: &str) -> usize {
-    let mut matrix: Matrix = input_into_matrix(input_string);
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct GuardConfig {
+    pub turn_policy: TurnPolicy,
+    pub collisions: Collisions,
+}
 
-    let mut p1_visited_coordinates = IndexSet::new();
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GuardState {
+    pub position: Position,
+    pub heading: Heading,
+    pub turns_taken: usize,
+}
+
+impl GuardState {
+    pub fn new(position: Position, heading: Heading) -> GuardState {
+        GuardState { position, heading, turns_taken: 0 }
+    }
 
-    let mut current_pos = (0, 0, '^');
-    for y in 0..matrix.len() {
-        for x in 0..matrix[y].len() {
-            // Assume starting pos is up
-            if matrix[y][x] == '^' {
-                current_pos = (x, y, '^');
-                p1_visited_coordinates.insert(current_pos);
+    fn turned(&self, policy: TurnPolicy) -> GuardState {
+        GuardState {
+            position: self.position,
+            heading: policy.turn(self.heading, self.turns_taken),
+            turns_taken: self.turns_taken + 1,
+        }
+    }
+
+    fn loop_key(&self, policy: TurnPolicy) -> (Position, Heading, usize) {
+        (self.position, self.heading, policy.phase(self.turns_taken))
+    }
+}
+
+pub type Position = (usize, usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PatrolOutcome {
+    Exits,
+    Loops,
+}
+
+// Lab map with precomputed jumps: for every cell and heading, the cell the guard stops at in front of the
+// next obstacle, or None if the guard walks off the map. The guard only has to be simulated from stop to stop.
+// The jumps don't know about portals, only the step by step walk does.
+pub struct GuardSimulator {
+    width: usize,
+    height: usize,
+    obstacles: Vec<bool>,
+    stops: [Vec<Option<Position>>; 4],
+    portals: HashMap<Position, Position>,
+}
+
+impl GuardSimulator {
+    pub fn new(width: usize, height: usize, obstacles: &[Position]) -> GuardSimulator {
+        let mut simulator = GuardSimulator {
+            width,
+            height,
+            obstacles: vec![false; width * height],
+            stops: std::array::from_fn(|_| vec![None; width * height]),
+            portals: HashMap::new(),
+        };
+        obstacles.iter().for_each(|&(x, y)| simulator.obstacles[y * width + x] = true);
+        (0..height).for_each(|y| simulator.recompute_row(y));
+        (0..width).for_each(|x| simulator.recompute_column(x));
+        simulator
+    }
+
+    pub fn is_obstacle(&self, (x, y): Position) -> bool {
+        self.obstacles[y * self.width + x]
+    }
+
+    // Only the jumps along the row and column of the obstacle can change
+    pub fn insert_obstacle(&mut self, (x, y): Position) {
+        self.obstacles[y * self.width + x] = true;
+        self.recompute_row(y);
+        self.recompute_column(x);
+    }
+
+    pub fn remove_obstacle(&mut self, (x, y): Position) {
+        self.obstacles[y * self.width + x] = false;
+        self.recompute_row(y);
+        self.recompute_column(x);
+    }
+
+    // A guard stepping onto either end comes out of the other one
+    pub fn insert_portal(&mut self, first: Position, second: Position) {
+        self.portals.insert(first, second);
+        self.portals.insert(second, first);
+    }
+
+    fn recompute_row(&mut self, y: usize) {
+        let cells: Vec<Position> = (0..self.width).map(|x| (x, y)).collect();
+        self.recompute_line(&cells, Heading::Left);
+        self.recompute_line(&cells.iter().rev().copied().collect::<Vec<_>>(), Heading::Right);
+    }
+
+    fn recompute_column(&mut self, x: usize) {
+        let cells: Vec<Position> = (0..self.height).map(|y| (x, y)).collect();
+        self.recompute_line(&cells, Heading::Up);
+        self.recompute_line(&cells.iter().rev().copied().collect::<Vec<_>>(), Heading::Down);
+    }
+
+    // `cells` are ordered so that the guard walking in `heading` would visit them in reverse
+    fn recompute_line(&mut self, cells: &[Position], heading: Heading) {
+        let mut stop = None;
+        for &(x, y) in cells {
+            let index = y * self.width + x;
+            if self.obstacles[index] {
+                stop = None;
+                self.stops[heading.index()][index] = None;
+                continue;
+            }
+            let ahead_blocked = self.step((x, y), heading).is_some_and(|ahead| self.is_obstacle(ahead));
+            if ahead_blocked {
+                stop = Some((x, y));
             }
+            self.stops[heading.index()][index] = stop;
+        }
+    }
+
+    pub fn step(&self, (x, y): Position, heading: Heading) -> Option<Position> {
+        let (dx, dy) = heading.delta();
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if nx < 0 || ny < 0 || nx >= self.width as isize || ny >= self.height as isize {
+            return None;
         }
+        Some((nx as usize, ny as usize))
     }
-    let start_pos = current_pos.clone();
 
-    loop {
-        let delta = get_movement_delta(current_pos.2);
-        let new_pos = (current_pos.0 as isize + delta.0 as isize, current_pos.1 as isize + delta.1 as isize);
+    pub fn next_stop(&self, (x, y): Position, heading: Heading) -> Option<Position> {
+        self.stops[heading.index()][y * self.width + x]
+    }
 
-        if new_pos.1 >= matrix[0].len() as isize || new_pos.0 >= matrix.len() as isize || new_pos.0 < 0 || new_pos.1 < 0 {
-            break;
+    // Jumps from obstacle to obstacle. The guard is looping as soon as it stops at the same place facing
+    // the same way twice.
+    pub fn patrol(&self, start: GuardState, policy: TurnPolicy) -> PatrolOutcome {
+        let mut seen_stops = HashSet::new();
+        let mut state = start;
+        loop {
+            match self.next_stop(state.position, state.heading) {
+                None => return PatrolOutcome::Exits,
+                Some(stop) => {
+                    state.position = stop;
+                    if !seen_stops.insert(state.loop_key(policy)) {
+                        return PatrolOutcome::Loops;
+                    }
+                    state = state.turned(policy);
+                }
+            }
         }
+    }
+
+    // Every state the guard is in, one step or turn at a time, until it leaves the map or starts repeating
+    // itself
+    pub fn walk(&self, start: GuardState, policy: TurnPolicy) -> Vec<GuardState> {
+        self.report(start, policy).states
+    }
 
-        match access(&matrix, new_pos.0 as usize, new_pos.1 as usize) {
-            '#' => {
-                let new_direction = turn(current_pos.2);
-                p1_visited_coordinates.insert((current_pos.0, current_pos.1, new_direction));
-                current_pos = (current_pos.0, current_pos.1, new_direction);
-            },
-            _ => {
-                p1_visited_coordinates.insert((new_pos.0 as usize, new_pos.1 as usize, current_pos.2));
-                current_pos = (new_pos.0 as usize, new_pos.1 as usize, current_pos.2);
+    pub fn report(&self, start: GuardState, policy: TurnPolicy) -> PatrolReport {
+        let mut first_seen = HashMap::new();
+        let mut states = vec![];
+        let mut state = start;
+        loop {
+            if let Some(&entry) = first_seen.get(&state.loop_key(policy)) {
+                let end = PatrolEnd::Loop { entry: states[entry], cycle_length: states.len() - entry };
+                return PatrolReport { states, end };
             }
+            first_seen.insert(state.loop_key(policy), states.len());
+            states.push(state);
+
+            state = match self.step(state.position, state.heading) {
+                None => return PatrolReport { states, end: PatrolEnd::Exit(state) },
+                Some(ahead) if self.is_obstacle(ahead) => state.turned(policy),
+                Some(ahead) => GuardState { position: self.portals.get(&ahead).copied().unwrap_or(ahead), ..state },
+            };
         }
     }
+}
 
-    // this time it also has unique coords + position as well
-    // println!("{:?}", p1_visited_coordinates);
-    let mut blockades = HashSet::new();
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PatrolEnd {
+    // Last state on the map, facing the edge it is about to walk off
+    Exit(GuardState),
+    // First state of the loop, and how many steps and turns it takes to get back to it
+    Loop { entry: GuardState, cycle_length: usize },
+}
 
-    for i in 1..p1_visited_coordinates.len() {
-        let mut visited_so_far = p1_visited_coordinates.clone();
-        visited_so_far.split_off(i);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatrolReport {
+    // One entry per step or turn, in order
+    pub states: Vec<GuardState>,
+    pub end: PatrolEnd,
+}
 
-        let current = p1_visited_coordinates.iter().nth(i).unwrap();
+impl PatrolReport {
+    // The states right after each turn
+    pub fn turn_points(&self) -> Vec<GuardState> {
+        self.states.windows(2)
+            .filter(|window| window[1].turns_taken != window[0].turns_taken)
+            .map(|window| window[1])
+            .collect()
+    }
+}
 
-        print!("{}\n", i);
+// The whole lab: obstacles, any number of guards and portals. A guard stepping onto a portal cell (a digit
+// on the map, each digit appears exactly twice) comes out of the other cell with the same digit.
+pub struct Lab {
+    simulator: GuardSimulator,
+    guards: Vec<GuardState>,
+}
 
-        // for each element try putting a blockade at the location and then simulate the iteration
-        // to see if it runs into a position already visited.
-        let blockade_pos = (current.0, current.1);
+impl Lab {
+    pub fn parse(input_string: &str) -> Lab {
+        let matrix = input_into_matrix(input_string);
+        let (width, height) = (matrix[0].len(), matrix.len());
+
+        let mut obstacles = vec![];
+        let mut guards = vec![];
+        let mut portal_ends: HashMap<char, Vec<Position>> = HashMap::new();
+        for y in 0..height {
+            for x in 0..width {
+                let c = access(&matrix, x, y);
+                if c == '#' {
+                    obstacles.push((x, y));
+                } else if c.is_ascii_digit() {
+                    portal_ends.entry(c).or_default().push((x, y));
+                } else if let Some(heading) = Heading::from_char(c) {
+                    guards.push(GuardState::new((x, y), heading));
+                }
+            }
+        }
 
-        let blockade_in_past_path = visited_so_far.iter()
-            .any(|visited| visited.0 == blockade_pos.0 && visited.1 == blockade_pos.1);
-        if blockade_in_past_path {
-            // we've tried it before, dont attempt again
-            continue;
+        let mut simulator = GuardSimulator::new(width, height, &obstacles);
+        for (c, ends) in portal_ends {
+            if ends.len() != 2 {
+                panic!("Portal {} needs exactly two ends, found {}", c, ends.len());
+            }
+            simulator.insert_portal(ends[0], ends[1]);
         }
 
-        // Disallow blockade at spawn point
-        if blockade_pos.0 == start_pos.0 && blockade_pos.1 == start_pos.1 {
-            continue;
+        if guards.is_empty() {
+            panic!("No guard on the map");
         }
+        Lab { simulator, guards }
+    }
 
-        matrix[blockade_pos.1][blockade_pos.0] = '#';
+    // The jump tables can only be used for a lone guard that never teleports
+    fn is_simple(&self) -> bool {
+        self.guards.len() == 1 && self.simulator.portals.is_empty()
+    }
 
-        let mut current_pos = visited_so_far.iter().last().unwrap().clone();
-        current_pos = (current_pos.0, current_pos.1, turn(current_pos.2));
+    // Cells visited by any guard before all of them have left, or before they start repeating themselves
+    pub fn visited_cells(&self, config: &GuardConfig) -> HashSet<Position> {
+        if self.is_simple() {
+            return self.simulator.walk(self.guards[0], config.turn_policy).iter()
+                .map(|state| state.position)
+                .collect();
+        }
+        self.simulate(config).1
+    }
 
-        loop {
+    // Cells where one extra obstacle makes the guards patrol forever
+    pub fn loop_obstacles(&mut self, config: &GuardConfig) -> Vec<Position> {
+        if self.is_simple() {
+            return self.loop_obstacles_single_guard(config.turn_policy);
+        }
 
-            // println!("{:?}", visited_so_far);
+        let starts: HashSet<Position> = self.guards.iter().map(|guard| guard.position).collect();
+        let candidates = self.visited_cells(config).into_iter()
+            .filter(|cell| !starts.contains(cell) && !self.simulator.portals.contains_key(cell))
+            .sorted()
+            .collect_vec();
+
+        candidates.into_iter()
+            .filter(|&candidate| {
+                self.simulator.insert_obstacle(candidate);
+                let (outcome, _) = self.simulate(config);
+                self.simulator.remove_obstacle(candidate);
+                outcome == PatrolOutcome::Loops
+            })
+            .collect()
+    }
 
-            let delta = get_movement_delta(current_pos.2);
-            let new_pos = (current_pos.0 as isize + delta.0 as isize, current_pos.1 as isize + delta.1 as isize);
+    // Patrol report of the lone guard, None if there are several of them or portals, see `is_simple`
+    pub fn patrol_report(&self, config: &GuardConfig) -> Option<PatrolReport> {
+        if !self.is_simple() {
+            return None;
+        }
+        Some(self.simulator.report(self.guards[0], config.turn_policy))
+    }
+
+    // Every loop causing obstruction together with the patrol it leads to
+    pub fn loop_reports(&mut self, config: &GuardConfig) -> Option<Vec<(Position, PatrolReport)>> {
+        if !self.is_simple() {
+            return None;
+        }
+        Some(self.loop_obstacles(config).into_iter()
+            .map(|obstruction| {
+                self.simulator.insert_obstacle(obstruction);
+                let report = self.simulator.report(self.guards[0], config.turn_policy);
+                self.simulator.remove_obstacle(obstruction);
+                (obstruction, report)
+            })
+            .collect())
+    }
+
+    // An extra obstruction given as x,y, which has to be inside the lab and not on top of a guard
+    fn parse_obstruction(&self, position: &str) -> Result<Position, String> {
+        let (x, y) = position.split(',')
+            .map(|coordinate| coordinate.parse::<usize>())
+            .collect_tuple()
+            .and_then(|(x, y)| x.ok().zip(y.ok()))
+            .ok_or(format!("Obstruction has to be given as x,y, got {}", position))?;
+        if x >= self.simulator.width || y >= self.simulator.height {
+            return Err(format!("Obstruction {},{} is outside the {}x{} lab", x, y, self.simulator.width, self.simulator.height));
+        }
+        if self.guards.iter().any(|guard| guard.position == (x, y)) {
+            return Err(format!("Obstruction {},{} is where a guard starts", x, y));
+        }
+        Ok((x, y))
+    }
+
+    // Draws the patrol onto the map the same way the puzzle description does: | and - for the direction the
+    // guard walked a cell in, + where it turned or crossed its own path, O for the extra obstruction and
+    // * for the start of the loop, unless the loop starts right at the guard's starting point.
+    pub fn render_report(&self, report: &PatrolReport, obstruction: Option<Position>) -> String {
+        let (width, height) = (self.simulator.width, self.simulator.height);
+        let mut map: Vec<Vec<char>> = (0..height)
+            .map(|y| (0..width).map(|x| if self.simulator.is_obstacle((x, y)) { '#' } else { '.' }).collect())
+            .collect();
+
+        let mut previous: Option<GuardState> = None;
+        for state in &report.states {
+            let (x, y) = state.position;
+            let mark = match state.heading {
+                _ if previous.is_some_and(|previous| previous.turns_taken != state.turns_taken) => '+',
+                Heading::Up | Heading::Down => '|',
+                Heading::Left | Heading::Right => '-',
+            };
+            map[y][x] = match map[y][x] {
+                '.' => mark,
+                existing if existing == mark => mark,
+                _ => '+',
+            };
+            previous = Some(*state);
+        }
+
+        if let PatrolEnd::Loop { entry, .. } = report.end {
+            map[entry.position.1][entry.position.0] = '*';
+        }
+        if let Some(start) = report.states.first() {
+            map[start.position.1][start.position.0] = match start.heading {
+                Heading::Up => '^',
+                Heading::Right => '>',
+                Heading::Down => 'v',
+                Heading::Left => '<',
+            };
+        }
+        if let Some((x, y)) = obstruction {
+            map[y][x] = 'O';
+        }
 
-            if new_pos.1 >= matrix[0].len() as isize || new_pos.0 >= matrix.len() as isize || new_pos.0 < 0 || new_pos.1 < 0 {
-                break;
+        map.iter().map(|row| row.iter().collect::<String>()).join("\n")
+    }
+
+    // Only cells on the original route can change anything. A blockade is tried the first time the route
+    // reaches its cell, starting the guard from just before it, since the route up to there is unaffected.
+    fn loop_obstacles_single_guard(&mut self, policy: TurnPolicy) -> Vec<Position> {
+        let start = self.guards[0];
+        let route = self.simulator.walk(start, policy);
+        let mut tried = HashSet::from([start.position]);
+        let mut blockades = vec![];
+
+        for window in route.windows(2) {
+            let (before, blockade) = (window[0], window[1].position);
+            if !tried.insert(blockade) {
+                continue;
             }
 
-            match access(&matrix, new_pos.0 as usize, new_pos.1 as usize) {
-                '#' => {
-                    let new_direction = turn(current_pos.2);
-                    if visited_so_far.iter().contains(&(current_pos.0, current_pos.1, new_direction)) {
-                        blockades.insert(blockade_pos);
-                        break;
-                    }
+            self.simulator.insert_obstacle(blockade);
+            if self.simulator.patrol(before, policy) == PatrolOutcome::Loops {
+                blockades.push(blockade);
+            }
+            self.simulator.remove_obstacle(blockade);
+        }
 
-                    visited_so_far.insert((current_pos.0, current_pos.1, new_direction));
-                    current_pos = (current_pos.0, current_pos.1, new_direction);
-                },
-                _ => {
-                    if visited_so_far.iter().contains(&(new_pos.0 as usize, new_pos.1 as usize, current_pos.2)) {
-                        blockades.insert(blockade_pos);
-                        break;
-                    }
+        blockades
+    }
 
-                    visited_so_far.insert((new_pos.0 as usize, new_pos.1 as usize, current_pos.2));
-                    current_pos = (new_pos.0 as usize, new_pos.1 as usize, current_pos.2);
-                }
+    // Guards walking through each other don't affect one another, so each one is followed on its own until it
+    // leaves or repeats itself. A guard only ever stands on a portal right after stepping onto the other end.
+    fn simulate(&self, config: &GuardConfig) -> (PatrolOutcome, HashSet<Position>) {
+        if config.collisions == Collisions::Block {
+            return self.simulate_blocking(config);
+        }
+
+        let mut outcome = PatrolOutcome::Exits;
+        let mut visited = HashSet::new();
+        for &guard in &self.guards {
+            let report = self.simulator.report(guard, config.turn_policy);
+            if matches!(report.end, PatrolEnd::Loop { .. }) {
+                outcome = PatrolOutcome::Loops;
+            }
+            for state in report.states {
+                visited.insert(state.position);
+                visited.extend(self.simulator.portals.get(&state.position));
+            }
+        }
+        (outcome, visited)
+    }
+
+    // Moves all guards one step or turn at a time, each in the order they appear on the map, until all of
+    // them have left the map or the whole lab is back in a state it has been in before
+    fn simulate_blocking(&self, config: &GuardConfig) -> (PatrolOutcome, HashSet<Position>) {
+        let policy = config.turn_policy;
+        let mut guards: Vec<Option<GuardState>> = self.guards.iter().copied().map(Some).collect();
+        let mut visited: HashSet<Position> = self.guards.iter().map(|guard| guard.position).collect();
+        let mut seen_states = HashSet::new();
+
+        while guards.iter().any(|guard| guard.is_some()) {
+            let key = guards.iter().map(|guard| guard.map(|guard| guard.loop_key(policy))).collect_vec();
+            if !seen_states.insert(key) {
+                return (PatrolOutcome::Loops, visited);
             }
 
+            for i in 0..guards.len() {
+                let Some(state) = guards[i] else { continue };
+                let Some(ahead) = self.simulator.step(state.position, state.heading) else {
+                    guards[i] = None;
+                    continue;
+                };
+
+                let occupied = guards.iter().enumerate()
+                    .any(|(j, other)| j != i && other.is_some_and(|other| other.position == ahead));
+                if self.simulator.is_obstacle(ahead) || occupied {
+                    guards[i] = Some(state.turned(policy));
+                    continue;
+                }
 
+                visited.insert(ahead);
+                let position = match self.simulator.portals.get(&ahead) {
+                    Some(&exit) => {
+                        visited.insert(exit);
+                        exit
+                    }
+                    None => ahead,
+                };
+                guards[i] = Some(GuardState { position, ..state });
+            }
         }
 
-        matrix[blockade_pos.1][blockade_pos.0] = '.';
+        (PatrolOutcome::Exits, visited)
     }
+}
 
-    println!("{:?}", blockades);
+fn run_part_one(input_string: &str) -> usize {
+    Lab::parse(input_string).visited_cells(&GuardConfig::default()).len()
+}
 
-    blockades.len()
+fn run_part_two(input_string: &str) -> usize {
+    Lab::parse(input_string).loop_obstacles(&GuardConfig::default()).len()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day06::{run_part_one, run_part_two};
+    use crate::day::day06::{run_part_one, run_part_two, Collisions, GuardConfig, GuardSimulator, GuardState, Heading, Lab, PatrolEnd, PatrolOutcome, TurnPolicy};
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -228,4 +666,135 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(6)), 1812);
     }
+
+    #[test]
+    fn test_jump_table_incremental_updates() {
+        let mut simulator = GuardSimulator::new(5, 5, &[(2, 0)]);
+        assert_eq!(simulator.next_stop((2, 4), Heading::Up), Some((2, 1)));
+        assert_eq!(simulator.next_stop((0, 0), Heading::Right), Some((1, 0)));
+        assert_eq!(simulator.next_stop((0, 0), Heading::Down), None);
+
+        simulator.insert_obstacle((2, 3));
+        assert_eq!(simulator.next_stop((2, 4), Heading::Up), Some((2, 4)));
+        assert_eq!(simulator.next_stop((2, 2), Heading::Down), Some((2, 2)));
+        assert_eq!(simulator.next_stop((0, 3), Heading::Right), Some((1, 3)));
+        assert_eq!(simulator.next_stop((4, 3), Heading::Left), Some((3, 3)));
+
+        simulator.remove_obstacle((2, 3));
+        assert_eq!(simulator.next_stop((2, 4), Heading::Up), Some((2, 1)));
+        assert_eq!(simulator.next_stop((0, 3), Heading::Right), None);
+    }
+
+    #[test]
+    fn test_patrol_loop_detection() {
+        // .#...
+        // ....#
+        // .^...
+        // #....
+        // ...#.
+        let mut simulator = GuardSimulator::new(5, 5, &[(1, 0), (4, 1), (0, 3), (3, 4)]);
+        assert_eq!(simulator.patrol(GuardState::new((1, 2), Heading::Up), TurnPolicy::Right), PatrolOutcome::Loops);
+
+        simulator.remove_obstacle((0, 3));
+        assert_eq!(simulator.patrol(GuardState::new((1, 2), Heading::Up), TurnPolicy::Right), PatrolOutcome::Exits);
+        assert_eq!(simulator.walk(GuardState::new((1, 2), Heading::Up), TurnPolicy::Right).len(), 12);
+    }
+
+    #[test]
+    fn test_turn_policies() {
+        let lab = Lab::parse(&example_input());
+        let left = GuardConfig { turn_policy: TurnPolicy::Left, ..GuardConfig::default() };
+        // Turning left at (4, 1) sends the guard straight off the left edge
+        assert_eq!(lab.visited_cells(&left).len(), 10);
+
+        let reverse = GuardConfig { turn_policy: TurnPolicy::Reverse, ..GuardConfig::default() };
+        assert_eq!(lab.visited_cells(&reverse).len(), 9);
+
+        let mut lab = Lab::parse("\
+.#...
+....#
+.^...
+#....
+...#.");
+        assert_eq!(lab.visited_cells(&GuardConfig::default()).len(), 8);
+        assert_eq!(lab.loop_obstacles(&GuardConfig::default()), vec![]);
+
+        let alternate = GuardConfig { turn_policy: TurnPolicy::Alternate, ..GuardConfig::default() };
+        assert_eq!(lab.visited_cells(&alternate).len(), 5);
+        // Boxed in on two sides, alternating turns spin the guard on the spot forever
+        assert_eq!(lab.loop_obstacles(&alternate), vec![(2, 1), (3, 0)]);
+    }
+
+    #[test]
+    fn test_multiple_guards_and_portals() {
+        let mut lab = Lab::parse("\
+.....
+.....
+.>.<.
+.....");
+        assert_eq!(lab.visited_cells(&GuardConfig::default()).len(), 5);
+        let blocking = GuardConfig { collisions: Collisions::Block, ..GuardConfig::default() };
+        // The right guard can't step onto the left one and turns up, the left guard then does the same
+        assert_eq!(lab.visited_cells(&blocking).len(), 6);
+        assert_eq!(lab.loop_obstacles(&blocking), vec![]);
+        assert!(lab.patrol_report(&GuardConfig::default()).is_none());
+        assert!(lab.loop_reports(&GuardConfig::default()).is_none());
+
+        let lab = Lab::parse("\
+.1...
+.....
+.^..1");
+        // Walks into the portal at the top and comes out at the bottom right, then exits
+        assert_eq!(lab.visited_cells(&GuardConfig::default()).len(), 6);
+        assert!(lab.patrol_report(&GuardConfig::default()).is_none());
+
+        // Two guards going round loops of 12 and 18 states that never meet, so blocking changes nothing
+        let lab = Lab::parse("\
+.#.....#.....
+....#.......#
+.^...........
+#......^.....
+...#..#......
+...........#.");
+        let (outcome, visited) = lab.simulate(&GuardConfig::default());
+        assert_eq!(outcome, PatrolOutcome::Loops);
+        assert_eq!(visited.len(), 8 + 14);
+        assert_eq!(lab.simulate(&blocking), (outcome, visited));
+    }
+
+    #[test]
+    fn test_patrol_report() {
+        let mut lab = Lab::parse(&example_input());
+        let report = lab.patrol_report(&GuardConfig::default()).unwrap();
+        // Start, 44 steps and 10 turns
+        assert_eq!(report.states.len(), 55);
+        assert_eq!(report.turn_points().len(), 10);
+        assert_eq!(report.turn_points()[0], GuardState { position: (4, 1), heading: Heading::Right, turns_taken: 1 });
+        assert_eq!(report.end, PatrolEnd::Exit(GuardState { position: (7, 9), heading: Heading::Down, turns_taken: 10 }));
+
+        assert_eq!(lab.parse_obstruction("3,6"), Ok((3, 6)));
+        assert!(lab.parse_obstruction("10,6").is_err());
+        assert!(lab.parse_obstruction("4,6").is_err());
+        assert!(lab.parse_obstruction("3;6").is_err());
+
+        let loops = lab.loop_reports(&GuardConfig::default()).unwrap();
+        assert_eq!(loops.len(), 6);
+        let (obstruction, report) = &loops[0];
+        assert_eq!(*obstruction, (3, 6));
+        assert_eq!(report.end, PatrolEnd::Loop {
+            entry: GuardState { position: (4, 6), heading: Heading::Up, turns_taken: 0 },
+            cycle_length: 22,
+        });
+        assert_eq!(lab.render_report(report, Some(*obstruction)), "\
+....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#...");
+    }
 }
@@ -1,96 +1,386 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use itertools::Itertools;
+use std::cell::RefCell;
+use num_bigint::{BigInt, BigUint};
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
 use crate::day::utils;
 
 pub fn run() {
     let input_string = utils::read_input(7);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+
+    // Other puzzle variants can be tried from the command line, e.g. `cargo run -- 7 ops=+,*,-,/ precedence`.
+    // `explain` shows how each calibration can be made.
+    let args = std::env::args().skip(2).collect_vec();
+    let mut config = EvaluationConfig::new(&PART_TWO_OPERATORS);
+    for arg in &args {
+        match arg.as_str() {
+            "precedence" => config.precedence = true,
+            "explain" => {}
+            _ => match arg.strip_prefix("ops=") {
+                Some(symbols) => config.operators = parse_operators(symbols),
+                None => panic!("Unknown option {}", arg),
+            },
+        }
+    }
+
+    if args.iter().any(|arg| arg != "explain") {
+        println!("{}", run_calibration_check(parse_input(&input_string), &config));
+    }
+    if args.iter().any(|arg| arg == "explain") {
+        print_solutions(&input_string, &config);
+    }
 }
 
 #[derive(Debug)]
 struct Calibration {
-    result: u64,
-    operands: Vec<u64>
+    result: BigInt,
+    operands: Vec<BigInt>
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Operator {
     Add,
     Multiply,
     Concatenate,
+    Subtract,
+    // Integer division rounding towards zero
+    Divide,
+    Xor,
+    Power,
 }
 
+const PART_ONE_OPERATORS: [Operator; 2] = [Operator::Add, Operator::Multiply];
+const PART_TWO_OPERATORS: [Operator; 3] = [Operator::Add, Operator::Multiply, Operator::Concatenate];
+
+// Exponentiation can make numbers arbitrarily large, so branches where a power would have this many bits more
+// than the calibration result are given up on. Only subtraction, division and xor could bring them back down.
+const MAX_POWER_EXCESS_BITS: u64 = 4096;
+
 impl Operator {
-    fn apply(&self, left: u64, right: u64) -> u64 {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Multiply => "*",
+            Operator::Concatenate => "||",
+            Operator::Subtract => "-",
+            Operator::Divide => "/",
+            Operator::Xor => "^",
+            Operator::Power => "**",
+        }
+    }
+
+    // Only used with standard precedence. Concatenation glues digits together, so it binds tightest.
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Xor => 0,
+            Operator::Add | Operator::Subtract => 1,
+            Operator::Multiply | Operator::Divide => 2,
+            Operator::Power => 3,
+            Operator::Concatenate => 4,
+        }
+    }
+
+    fn is_right_associative(&self) -> bool {
+        *self == Operator::Power
+    }
+
+    // None when the result is undefined, e.g. division by zero, or a power would exceed `max_bits`
+    fn apply(&self, left: &BigInt, right: &BigInt, max_bits: u64) -> Option<BigInt> {
         match self {
-            Operator::Add => {left + right}
-            Operator::Multiply => {left * right}
+            Operator::Add => Some(left + right),
+            Operator::Multiply => Some(left * right),
             Operator::Concatenate => {
-                let digit_count = match right {
-                    0 => 1,
-                    _ => (right as f64).log10().floor() as u32 + 1
-                };
+                if right.is_negative() {
+                    return None;
+                }
+                let shifted = left * power_of_ten(digit_count(right));
+                Some(if left.is_negative() { shifted - right } else { shifted + right })
+            }
+            Operator::Subtract => Some(left - right),
+            Operator::Divide => if right.is_zero() { None } else { Some(left / right) },
+            Operator::Xor => Some(left ^ right),
+            Operator::Power => {
+                if right.is_negative() {
+                    return None;
+                }
+                // 0, 1 and -1 stay small however large the exponent
+                if left.magnitude() <= &BigUint::one() {
+                    return Some(match left.is_negative() && right.bit(0) {
+                        _ if right.is_zero() => BigInt::one(),
+                        true => -BigInt::one(),
+                        false => left.clone(),
+                    });
+                }
+                // The power has more than (bits - 1) * exponent bits
+                let exponent = right.to_u32()?;
+                if (left.bits() - 1).saturating_mul(exponent as u64) >= max_bits {
+                    return None;
+                }
+                Some(left.pow(exponent))
+            }
+        }
+    }
 
-                left * 10_u64.pow(digit_count) + right
+    // Given the result of `left op right` and `right`, works out `left`. None if no left operand could
+    // have produced the result. Only needed for the operators of the original puzzle, where every value
+    // stays non negative.
+    fn undo(&self, result: &BigInt, right: &BigInt) -> Option<BigInt> {
+        match self {
+            Operator::Add => Some(result - right).filter(|left| !left.is_negative()),
+            Operator::Multiply => {
+                if right.is_zero() || !(result % right).is_zero() {
+                    return None;
+                }
+                Some(result / right)
             }
+            Operator::Concatenate => {
+                let magnitude = power_of_ten(digit_count(right));
+                if &(result % &magnitude) == right {
+                    Some(result / magnitude)
+                } else {
+                    None
+                }
+            }
+            _ => None,
         }
     }
 }
 
-fn produce_combinations(size: usize, with_concat: bool) -> Vec<Vec<Operator>> {
-    _produce_combinations(size, with_concat, vec![])
+thread_local! {
+    static POWERS_OF_TEN: RefCell<Vec<BigInt>> = RefCell::new(vec![BigInt::one()]);
 }
 
-fn _produce_combinations(size: usize, with_concat: bool, accumulator: Vec<Operator>) -> Vec<Vec<Operator>> {
-    if size == 0 {
-        return vec![accumulator];
+fn power_of_ten(exponent: u32) -> BigInt {
+    POWERS_OF_TEN.with_borrow_mut(|powers| {
+        while powers.len() <= exponent as usize {
+            let next = powers.last().unwrap() * 10;
+            powers.push(next);
+        }
+        powers[exponent as usize].clone()
+    })
+}
+
+fn digit_count(value: &BigInt) -> u32 {
+    if let Some(small) = value.magnitude().to_u64() {
+        return small.checked_ilog10().map_or(1, |log| log + 1);
+    }
+    // 2^(bits - 1) <= value < 2^bits leaves only two possible digit counts
+    let at_least = ((value.bits() - 1) as f64 * std::f64::consts::LOG10_2) as u32 + 1;
+    if value.magnitude() >= power_of_ten(at_least).magnitude() {
+        at_least + 1
+    } else {
+        at_least
     }
+}
 
-    let mut results = Vec::new();
+fn parse_operators(symbols: &str) -> Vec<Operator> {
+    let all = [Operator::Add, Operator::Multiply, Operator::Concatenate, Operator::Subtract, Operator::Divide,
+        Operator::Xor, Operator::Power];
+    symbols.split(',')
+        .map(|symbol| *all.iter().find(|operator| operator.symbol() == symbol)
+            .unwrap_or_else(|| panic!("Unknown operator {}", symbol)))
+        .unique()
+        .collect()
+}
 
-    let mut vec1 = accumulator.clone();
-    vec1.push(Operator::Multiply);
-    results.append(&mut _produce_combinations(size - 1, with_concat, vec1));
+#[derive(Debug, Clone)]
+struct EvaluationConfig {
+    operators: Vec<Operator>,
+    // Standard operator precedence instead of strictly left to right
+    precedence: bool,
+}
 
-    let mut vec2 = accumulator.clone();
-    vec2.push(Operator::Add);
-    results.append(&mut _produce_combinations(size - 1, with_concat, vec2));
+impl EvaluationConfig {
+    fn new(operators: &[Operator]) -> EvaluationConfig {
+        EvaluationConfig { operators: operators.to_vec(), precedence: false }
+    }
 
-    if with_concat {
-        let mut vec3 = accumulator.clone();
-        vec3.push(Operator::Concatenate);
-        results.append(&mut _produce_combinations(size - 1, with_concat, vec3));
+    // Working backwards only works when every operator can be undone and values never go negative
+    fn can_solve_backwards(&self) -> bool {
+        !self.precedence && self.operators.iter()
+            .all(|operator| matches!(operator, Operator::Add | Operator::Multiply | Operator::Concatenate))
     }
+}
 
-    results
+// Expression evaluated up to some operand. With precedence, operators that can't be applied yet wait on a
+// stack together with their left operand, with strictly increasing precedence towards the top.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PartialValue {
+    pending: Vec<(BigInt, Operator)>,
+    value: BigInt,
 }
 
-fn run_calibration_check(calibration_inputs: Vec<Calibration>, with_concat: bool) -> u64 {
-    let mut sum = 0;
-    let mut operator_combinations_map = HashMap::new();
+impl PartialValue {
+    fn push(&self, operator: Operator, operand: &BigInt, precedence: bool, max_bits: u64) -> Option<PartialValue> {
+        if !precedence {
+            return Some(PartialValue { pending: vec![], value: operator.apply(&self.value, operand, max_bits)? });
+        }
 
-    for calibration_input in calibration_inputs {
-        let operators_count = calibration_input.operands.len() - 1;
-        let operator_combinations = operator_combinations_map
-            .entry(operators_count)
-            .or_insert_with_key(|count| produce_combinations(*count, with_concat));
+        let mut pending = self.pending.clone();
+        let mut value = self.value.clone();
+        while let Some(&(_, top)) = pending.last() {
+            let binds_tighter = top.precedence() > operator.precedence()
+                || (top.precedence() == operator.precedence() && !operator.is_right_associative());
+            if !binds_tighter {
+                break;
+            }
+            let (left, top) = pending.pop().unwrap();
+            value = top.apply(&left, &value, max_bits)?;
+        }
+        pending.push((value, operator));
+        Some(PartialValue { pending, value: operand.clone() })
+    }
 
-        for operator_combination in operator_combinations {
-            let mut combination_iterator = operator_combination.iter();
-            let combination_total = calibration_input.operands.iter()
-                .skip(1)
-                .fold(calibration_input.operands[0], |acc, val| {
-                    combination_iterator.next().unwrap().apply(acc, *val)
-                });
+    fn finish(&self, max_bits: u64) -> Option<BigInt> {
+        let mut value = self.value.clone();
+        for (left, operator) in self.pending.iter().rev() {
+            value = operator.apply(left, &value, max_bits)?;
+        }
+        Some(value)
+    }
+}
 
-            if combination_total == calibration_input.result {
-                sum += combination_total;
-                break;
+// Partial values after some number of operands, each with how many operator sequences lead to it and the
+// index of one predecessor in the previous layer together with the operator applied to it
+type ForwardLayer = IndexMap<PartialValue, (BigUint, Option<(usize, Operator)>)>;
+
+impl Calibration {
+    fn solve(&self, config: &EvaluationConfig) -> Option<Vec<Operator>> {
+        if !config.can_solve_backwards() {
+            return self.solve_forwards(config).1;
+        }
+
+        let mut sequence = vec![];
+        if self.solve_prefix(self.operands.len() - 1, &self.result, &config.operators, &mut sequence) {
+            Some(sequence)
+        } else {
+            None
+        }
+    }
+
+    // Number of distinct operator sequences that produce the result
+    fn count_solutions(&self, config: &EvaluationConfig) -> BigUint {
+        if !config.can_solve_backwards() {
+            return self.solve_forwards(config).0;
+        }
+        self.count_prefix_solutions(self.operands.len() - 1, &self.result, &config.operators)
+    }
+
+    // Works backwards from the result, peeling off the last operand with every operator that could have
+    // produced it. Most branches die immediately on divisibility or suffix checks, unlike trying every
+    // operator sequence front to back.
+    fn solve_prefix(&self, last: usize, result: &BigInt, operators: &[Operator], sequence: &mut Vec<Operator>) -> bool {
+        if last == 0 {
+            return &self.operands[0] == result;
+        }
+
+        let right = &self.operands[last];
+        if right.is_zero() && result.is_zero() && operators.contains(&Operator::Multiply) {
+            // Anything times zero is zero, so the operators before don't matter
+            sequence.extend(std::iter::repeat_n(operators[0], last - 1));
+            sequence.push(Operator::Multiply);
+            return true;
+        }
+
+        for &operator in operators {
+            let Some(left) = operator.undo(result, right) else { continue };
+            if self.solve_prefix(last - 1, &left, operators, sequence) {
+                sequence.push(operator);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn count_prefix_solutions(&self, last: usize, result: &BigInt, operators: &[Operator]) -> BigUint {
+        if last == 0 {
+            return BigUint::from((&self.operands[0] == result) as u8);
+        }
+
+        let right = &self.operands[last];
+        let mut count = BigUint::zero();
+        if right.is_zero() && result.is_zero() && operators.contains(&Operator::Multiply) {
+            count += BigUint::from(operators.len()).pow(last - 1);
+        }
+        for &operator in operators {
+            if let Some(left) = operator.undo(result, right) {
+                count += self.count_prefix_solutions(last - 1, &left, operators);
+            }
+        }
+        count
+    }
+
+    // For operators that can't be undone, evaluates every operator sequence front to back. Sequences that
+    // reach the same partial value are merged, keeping count of how many there were and one way to get
+    // there. Returns the number of solutions and one of them.
+    fn solve_forwards(&self, config: &EvaluationConfig) -> (BigUint, Option<Vec<Operator>>) {
+        let max_bits = self.result.bits() + MAX_POWER_EXCESS_BITS;
+        let start = PartialValue { pending: vec![], value: self.operands[0].clone() };
+        let mut layers: Vec<ForwardLayer> = vec![IndexMap::from([(start, (BigUint::one(), None))])];
+
+        for operand in &self.operands[1..] {
+            let mut next_layer = ForwardLayer::new();
+            for (index, (partial, (count, _))) in layers.last().unwrap().iter().enumerate() {
+                for &operator in &config.operators {
+                    let Some(next) = partial.push(operator, operand, config.precedence, max_bits) else { continue };
+                    next_layer.entry(next).or_insert((BigUint::zero(), Some((index, operator)))).0 += count;
+                }
+            }
+            layers.push(next_layer);
+        }
+
+        let mut count = BigUint::zero();
+        let mut witness = None;
+        for (index, (partial, (partial_count, _))) in layers.last().unwrap().iter().enumerate() {
+            if partial.finish(max_bits).as_ref() != Some(&self.result) {
+                continue;
+            }
+            count += partial_count;
+            if witness.is_none() {
+                witness = Some(index);
+            }
+        }
+
+        let witness = witness.map(|mut index| {
+            let mut sequence = vec![];
+            for layer in layers.iter().rev() {
+                let Some((parent, operator)) = layer[index].1 else { break };
+                sequence.push(operator);
+                index = parent;
             }
+            sequence.reverse();
+            sequence
+        });
+        (count, witness)
+    }
+
+    fn format_expression(&self, sequence: &[Operator]) -> String {
+        let mut expression = self.operands[0].to_string();
+        for (operator, operand) in sequence.iter().zip(self.operands.iter().skip(1)) {
+            expression.push_str(&format!(" {} {}", operator.symbol(), operand));
+        }
+        expression
+    }
+}
+
+fn run_calibration_check(calibration_inputs: Vec<Calibration>, config: &EvaluationConfig) -> BigInt {
+    calibration_inputs.into_iter()
+        .filter(|calibration| calibration.solve(config).is_some())
+        .map(|calibration| calibration.result)
+        .sum()
+}
+
+fn print_solutions(input_string: &str, config: &EvaluationConfig) {
+    for calibration in parse_input(input_string) {
+        match calibration.solve(config) {
+            Some(sequence) => println!("{} = {} ({} ways)", calibration.result,
+                                       calibration.format_expression(&sequence),
+                                       calibration.count_solutions(config)),
+            None => println!("{} can't be made from {}", calibration.result, calibration.operands.iter().join(" ")),
         }
     }
-    sum
 }
 
 fn parse_input(input_string: &str) -> Vec<Calibration> {
@@ -108,21 +398,33 @@ fn parse_input(input_string: &str) -> Vec<Calibration> {
 
 fn run_part_one(input_string: &str) -> usize {
     let calibration_inputs = parse_input(input_string);
-    let sum = run_calibration_check(calibration_inputs, false);
-    sum.try_into().unwrap()
+    let sum = run_calibration_check(calibration_inputs, &EvaluationConfig::new(&PART_ONE_OPERATORS));
+    sum.to_usize().unwrap()
 }
 
 fn run_part_two(input_string: &str) -> usize {
     let calibration_inputs = parse_input(input_string);
-    let sum = run_calibration_check(calibration_inputs, true);
-    sum.try_into().unwrap()
+    let sum = run_calibration_check(calibration_inputs, &EvaluationConfig::new(&PART_TWO_OPERATORS));
+    sum.to_usize().unwrap()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day07::{run_part_one, run_part_two};
+    use num_bigint::{BigInt, BigUint};
+    use num_traits::Pow;
+    use crate::day::day07::{digit_count, parse_input, parse_operators, run_part_one, run_part_two, Calibration, EvaluationConfig, Operator, PART_ONE_OPERATORS, PART_TWO_OPERATORS};
     use crate::day::utils;
 
+    fn calibration(line: &str) -> Calibration {
+        parse_input(line).pop().unwrap()
+    }
+
+    fn solve(line: &str, operators: &str, precedence: bool) -> Option<String> {
+        let calibration = calibration(line);
+        let config = EvaluationConfig { operators: parse_operators(operators), precedence };
+        calibration.solve(&config).map(|sequence| calibration.format_expression(&sequence))
+    }
+
     fn example_input() -> String {
         String::from("\
 190: 10 19
@@ -155,4 +457,108 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(7)), 401477450831495);
     }
+
+    #[test]
+    fn test_solution_expressions() {
+        let calibrations = parse_input(&example_input());
+        let expressions: Vec<_> = calibrations.iter()
+            .map(|calibration| calibration.solve(&EvaluationConfig::new(&PART_TWO_OPERATORS))
+                .map(|sequence| calibration.format_expression(&sequence)))
+            .collect();
+        assert_eq!(expressions[0], Some("10 * 19".to_string()));
+        assert_eq!(expressions[2], None);
+        assert_eq!(expressions[4], Some("6 * 8 || 6 * 15".to_string()));
+        assert_eq!(calibrations[1].solve(&EvaluationConfig::new(&PART_ONE_OPERATORS)), Some(vec![Operator::Multiply, Operator::Add]));
+        assert_eq!(calibrations[1].format_expression(&[Operator::Add, Operator::Multiply]), "81 + 40 * 27");
+    }
+
+    #[test]
+    fn test_solution_counts() {
+        let calibrations = parse_input(&example_input());
+        // 81 + 40 * 27 and 81 * 40 + 27
+        assert_eq!(calibrations[1].count_solutions(&EvaluationConfig::new(&PART_ONE_OPERATORS)), 2u32.into());
+        assert_eq!(calibrations[2].count_solutions(&EvaluationConfig::new(&PART_TWO_OPERATORS)), 0u32.into());
+
+        let with_zero = calibration("0: 3 4 5 0");
+        assert_eq!(with_zero.count_solutions(&EvaluationConfig::new(&PART_TWO_OPERATORS)), 9u32.into());
+        assert_eq!(with_zero.solve(&EvaluationConfig::new(&PART_ONE_OPERATORS)), Some(vec![Operator::Add, Operator::Add, Operator::Multiply]));
+
+        let ones = calibration("2: 1 1 1");
+        // 1 * 1 + 1 and 1 + 1 * 1
+        assert_eq!(ones.count_solutions(&EvaluationConfig::new(&PART_TWO_OPERATORS)), 2u32.into());
+    }
+
+    #[test]
+    fn test_forwards_matches_backwards() {
+        for calibration in parse_input(&example_input()) {
+            let config = EvaluationConfig::new(&PART_TWO_OPERATORS);
+            let (count, witness) = calibration.solve_forwards(&config);
+            assert_eq!(count, calibration.count_solutions(&config));
+            assert_eq!(witness.is_some(), calibration.solve(&config).is_some());
+        }
+    }
+
+    #[test]
+    fn test_extra_operators() {
+        assert_eq!(solve("5: 10 2 3", "+,-,*,/", false), Some("10 - 2 - 3".to_string()));
+        assert_eq!(calibration("5: 10 2 3").count_solutions(&EvaluationConfig {
+            operators: parse_operators("+,-,*,/"),
+            precedence: false,
+        }), 1u32.into());
+        assert_eq!(solve("100: 10 4 3", "+,-,*,/", false), None);
+        assert_eq!(solve("0: 10 4 3", "/", false), Some("10 / 4 / 3".to_string()));
+        assert_eq!(solve("6: 5 3", "+,^", false), Some("5 ^ 3".to_string()));
+        assert_eq!(solve("-7: 3 10", "-,||", false), Some("3 - 10".to_string()));
+        assert_eq!(solve("512: 2 3 3", "**", false), Some("2 ** 3 ** 3".to_string()));
+        assert_eq!(solve("512: 2 3 3", "**", true), None);
+        assert_eq!(solve("134217728: 2 3 3", "**", true), Some("2 ** 3 ** 3".to_string()));
+    }
+
+    #[test]
+    fn test_huge_powers() {
+        // Tiny results from exponents far too large to evaluate for other bases
+        assert_eq!(solve("1: 1 5000", "**", false), Some("1 ** 5000".to_string()));
+        assert_eq!(solve("-1: 0 1 5001", "-,**", false), Some("0 - 1 ** 5001".to_string()));
+        assert_eq!(solve("5: 0 5000000000 5", "**,+", false), Some("0 ** 5000000000 + 5".to_string()));
+        assert_eq!(solve("1: 2 5000", "**", false), None);
+        // Large results are fine as long as the powers don't outgrow them
+        let line = format!("{}: 2 5000", BigInt::from(2).pow(5000u32));
+        assert_eq!(solve(&line, "+,**", false), Some("2 ** 5000".to_string()));
+    }
+
+    #[test]
+    fn test_digit_count() {
+        for digits in 1..400 {
+            let power = BigInt::from(10).pow(digits);
+            assert_eq!(digit_count(&power), digits + 1);
+            assert_eq!(digit_count(&(&power - 1)), digits);
+            assert_eq!(digit_count(&-power), digits + 1);
+        }
+        assert_eq!(digit_count(&BigInt::from(0)), 1);
+    }
+
+    #[test]
+    fn test_counts_beyond_u64() {
+        // Everything in front of the final zero is multiplied away, 3^40 ways
+        let line = format!("0: {} 0", ["1"; 41].join(" "));
+        assert_eq!(calibration(&line).count_solutions(&EvaluationConfig::new(&PART_TWO_OPERATORS)), BigUint::from(3u32).pow(40u32));
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(solve("3267: 81 40 27", "+,*", true), Some("81 * 40 + 27".to_string()));
+        assert_eq!(solve("1161: 81 40 27", "+,*", true), Some("81 + 40 * 27".to_string()));
+        assert_eq!(solve("1161: 81 40 27", "+,*", false), None);
+        // Concatenation binds tightest: 1 + (2 || 3) * 4
+        assert_eq!(solve("93: 1 2 3 4", "+,*,||", true), Some("1 + 2 || 3 * 4".to_string()));
+        assert_eq!(solve("3: 12 2 3", "-,/", true), Some("12 / 2 - 3".to_string()));
+    }
+
+    #[test]
+    fn test_results_beyond_u64() {
+        let line = "9999999999800000000001000: 99999999999 99999999999 1000";
+        assert_eq!(solve(line, "+,*", false), Some("99999999999 * 99999999999 * 1000".to_string()));
+        assert_eq!(solve(line, "+,-,*", false), Some("99999999999 * 99999999999 * 1000".to_string()));
+        assert_eq!(calibration(line).result, "9999999999800000000001000".parse::<BigInt>().unwrap());
+    }
 }
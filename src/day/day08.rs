@@ -1,49 +1,117 @@
 use crate::day::utils;
+use gcd::Gcd;
 use itertools::Itertools;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 pub fn run() {
     let input_string = utils::read_input(8);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+
+    // Other antinode rules and antenna lists can be tried from the command line, e.g.
+    // `cargo run -- 8 harmonics=2..5 reduce list=antennas.txt`. `overlay` draws the antinodes of the puzzle
+    // input and prints statistics per frequency.
+    let args = std::env::args().skip(2).collect_vec();
+    if args.is_empty() {
+        return;
+    }
+    let mut config = PART_TWO_CONFIG;
+    let mut list_file = None;
+    let mut overlay = false;
+    for arg in &args {
+        if arg == "overlay" {
+            overlay = true;
+        } else if arg == "reduce" {
+            config.reduce_step = true;
+        } else if let Some(range) = arg.strip_prefix("harmonics=") {
+            let (first, last) = range.split_once("..").expect("Harmonics have to be given as first..last");
+            config.first_harmonic = first.parse().unwrap();
+            config.last_harmonic = if last.is_empty() { None } else { Some(last.parse().unwrap()) };
+        } else if let Some(file) = arg.strip_prefix("list=") {
+            list_file = Some(file.to_string());
+        } else {
+            panic!("Unknown option {}", arg);
+        }
+    }
+
+    match list_file {
+        None if overlay => print_overlay(&input_string, &config),
+        None => {
+            let (antennas, bounds) = parse_map(&input_string);
+            println!("{}", find_antinodes(&antennas, bounds, &config).len());
+        }
+        Some(file) => {
+            let list = std::fs::read_to_string(file).expect("Failed to read antenna list");
+            let dimensions = list.lines().next().map_or(0, |line| line.split(',').count());
+            match dimensions {
+                2 => {
+                    let (antennas, bounds) = parse_antenna_list::<2>(&list);
+                    println!("{}", find_antinodes(&antennas, bounds, &config).len());
+                }
+                3 => {
+                    let (antennas, bounds) = parse_antenna_list::<3>(&list);
+                    println!("{}", find_antinodes(&antennas, bounds, &config).len());
+                }
+                _ => panic!("Only 2D and 3D antenna lists are supported"),
+            }
+        }
+    }
 }
 
+type Point<const D: usize> = [isize; D];
+
 #[derive(Debug)]
-struct Antenna {
-    x: isize,
-    y: isize,
+struct Antenna<const D: usize> {
+    position: Point<D>,
     label: char,
 }
 
-impl Antenna {
-    fn position_delta(&self, other: &Antenna) -> (isize, isize) {
-        (other.x - self.x, other.y - self.y)
+impl<const D: usize> Antenna<D> {
+    fn position_delta(&self, other: &Antenna<D>) -> Point<D> {
+        std::array::from_fn(|i| other.position[i] - self.position[i])
     }
 
-    fn apply_n_times_delta(&self, (dx, dy): (isize, isize), n: isize) -> (isize, isize) {
-        (self.x + n * dx, self.y + n * dy)
+    fn apply_n_times_delta(&self, delta: Point<D>, n: isize) -> Point<D> {
+        std::array::from_fn(|i| self.position[i] + n * delta[i])
     }
 }
 
-fn simulate(input_string: &str, with_resonance: bool) -> usize {
-    let mut antinodes: HashSet<(isize, isize)> = HashSet::new();
-    let mut antennas: Vec<Antenna> = Vec::new();
+// Antinodes of a pair of antennas lie on the line from one antenna through the other, at whole multiples
+// (harmonics) of the step between them, counted from the first antenna. The step is the distance between
+// the antennas, or with `reduce_step` the smallest grid step along the line, which also puts antinodes
+// between the antennas.
+#[derive(Debug, Clone, Copy)]
+struct AntinodeConfig {
+    first_harmonic: isize,
+    // None keeps going until the edge of the map
+    last_harmonic: Option<isize>,
+    reduce_step: bool,
+}
 
-    let y_max = input_string.lines().count() as isize;
-    let x_max = input_string.lines().nth(0).unwrap().chars().count() as isize;
+const PART_ONE_CONFIG: AntinodeConfig = AntinodeConfig { first_harmonic: 2, last_harmonic: Some(2), reduce_step: false };
+const PART_TWO_CONFIG: AntinodeConfig = AntinodeConfig { first_harmonic: 1, last_harmonic: None, reduce_step: false };
 
-    for (y, line) in input_string.lines().enumerate() {
-        for (x, label) in line.char_indices() {
-            if label != '.' {
-                antennas.push(Antenna { x: x as isize, y: y as isize, label });
-            }
-        }
-    }
+fn reduce<const D: usize>(delta: Point<D>) -> Point<D> {
+    let divisor = delta.iter().fold(0usize, |divisor, component| divisor.gcd(component.unsigned_abs()));
+    std::array::from_fn(|i| delta[i] / divisor as isize)
+}
+
+fn in_bounds<const D: usize>(point: &Point<D>, bounds: &Point<D>) -> bool {
+    point.iter().zip(bounds).all(|(&coordinate, &bound)| coordinate >= 0 && coordinate < bound)
+}
+
+fn find_antinodes<const D: usize>(antennas: &[Antenna<D>], bounds: Point<D>, config: &AntinodeConfig) -> HashSet<Point<D>> {
+    antinodes_by_frequency(antennas, bounds, config).into_values().flatten().collect()
+}
+
+fn antinodes_by_frequency<const D: usize>(antennas: &[Antenna<D>], bounds: Point<D>, config: &AntinodeConfig) -> BTreeMap<char, HashSet<Point<D>>> {
+    let mut antinodes_by_frequency = BTreeMap::new();
 
     antennas.iter()
         .map(|antenna| antenna.label)
         .unique()
         .for_each(|antenna_label| {
+            let antinodes: &mut HashSet<Point<D>> = antinodes_by_frequency.entry(antenna_label).or_default();
             // for each unique antenna label, check the antennas for same label antennas, then calculate coordinate deltas between all permutations by 2.
             let permutations = antennas.iter()
                 .filter(|antenna| antenna.label == antenna_label)
@@ -51,40 +119,156 @@ fn simulate(input_string: &str, with_resonance: bool) -> usize {
             for permutation in permutations {
                 let position = permutation[0];
                 let other_position = permutation[1];
-                let delta = position.position_delta(other_position);
+                let mut delta = position.position_delta(other_position);
+                if delta.iter().all(|&component| component == 0) {
+                    continue;
+                }
+                if config.reduce_step {
+                    delta = reduce(delta);
+                }
 
-                let mut delta_multiplier = if with_resonance { 1 } else { 2 };
-                loop {
+                let mut delta_multiplier = config.first_harmonic;
+                while config.last_harmonic.is_none_or(|last| delta_multiplier <= last) {
                     let antinode_pos = position.apply_n_times_delta(delta, delta_multiplier);
-                    if antinode_pos.0 >= 0 && antinode_pos.0 < x_max && antinode_pos.1 >= 0 && antinode_pos.1 < y_max {
-                        antinodes.insert(antinode_pos);
+                    if !in_bounds(&antinode_pos, &bounds) {
+                        // Harmonics only move further away from here on, unless we haven't reached the map yet
+                        if delta_multiplier > 0 {
+                            break;
+                        }
                     } else {
-                        break;
-                    }
-
-                    if !with_resonance {
-                        break;
+                        antinodes.insert(antinode_pos);
                     }
                     delta_multiplier += 1;
                 }
             }
         });
 
-    antinodes.len()
+    antinodes_by_frequency
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct FrequencyStats {
+    frequency: char,
+    antennas: usize,
+    antinodes: usize,
+    // Antinodes that some other frequency also produces
+    shared_antinodes: usize,
+}
+
+fn frequency_stats(antennas: &[Antenna<2>], antinodes: &BTreeMap<char, HashSet<Point<2>>>) -> Vec<FrequencyStats> {
+    let coincidences = coinciding_antinodes(antinodes);
+    antinodes.iter()
+        .map(|(&frequency, positions)| FrequencyStats {
+            frequency,
+            antennas: antennas.iter().filter(|antenna| antenna.label == frequency).count(),
+            antinodes: positions.len(),
+            shared_antinodes: positions.iter().filter(|position| coincidences.contains_key(*position)).count(),
+        })
+        .collect()
+}
+
+// Positions that are antinodes of more than one frequency, with those frequencies
+fn coinciding_antinodes(antinodes: &BTreeMap<char, HashSet<Point<2>>>) -> BTreeMap<Point<2>, Vec<char>> {
+    let mut frequencies_at: BTreeMap<Point<2>, Vec<char>> = BTreeMap::new();
+    for (&frequency, positions) in antinodes {
+        for &position in positions {
+            frequencies_at.entry(position).or_default().push(frequency);
+        }
+    }
+    frequencies_at.retain(|_, frequencies| frequencies.len() > 1);
+    frequencies_at
+}
+
+// Draws the map with antinodes as # like the puzzle description, only for the given frequency if there is
+// one. Antinodes of several frequencies at the same spot are drawn as *, antennas always win.
+fn render_antinodes(antennas: &[Antenna<2>], bounds: Point<2>, antinodes: &BTreeMap<char, HashSet<Point<2>>>, only: Option<char>) -> String {
+    let [width, height] = bounds;
+    let mut map = vec![vec!['.'; width as usize]; height as usize];
+
+    let included = |frequency: char| only.is_none_or(|only| only == frequency);
+    for (_, positions) in antinodes.iter().filter(|(&frequency, _)| included(frequency)) {
+        for &[x, y] in positions {
+            let cell = &mut map[y as usize][x as usize];
+            *cell = if *cell == '.' { '#' } else { '*' };
+        }
+    }
+    for antenna in antennas.iter().filter(|antenna| included(antenna.label)) {
+        let [x, y] = antenna.position;
+        map[y as usize][x as usize] = antenna.label;
+    }
+
+    map.iter().map(|row| row.iter().collect::<String>()).join("\n")
+}
+
+fn print_overlay(input_string: &str, config: &AntinodeConfig) {
+    let (antennas, bounds) = parse_map(input_string);
+    let antinodes = antinodes_by_frequency(&antennas, bounds, config);
+
+    for &frequency in antinodes.keys() {
+        println!("{}\n{}\n", frequency, render_antinodes(&antennas, bounds, &antinodes, Some(frequency)));
+    }
+    println!("{}\n", render_antinodes(&antennas, bounds, &antinodes, None));
+
+    println!("frequency antennas antinodes shared");
+    for stats in frequency_stats(&antennas, &antinodes) {
+        println!("{:>9} {:>8} {:>9} {:>6}", stats.frequency, stats.antennas, stats.antinodes, stats.shared_antinodes);
+    }
+    for ([x, y], frequencies) in coinciding_antinodes(&antinodes) {
+        println!("{},{}: {}", x, y, frequencies.iter().join(" "));
+    }
+}
+
+fn parse_map(input_string: &str) -> (Vec<Antenna<2>>, Point<2>) {
+    let mut antennas: Vec<Antenna<2>> = Vec::new();
+
+    let y_max = input_string.lines().count() as isize;
+    let x_max = input_string.lines().next().unwrap().chars().count() as isize;
+
+    for (y, line) in input_string.lines().enumerate() {
+        for (x, label) in line.char_indices() {
+            if label != '.' {
+                antennas.push(Antenna { position: [x as isize, y as isize], label });
+            }
+        }
+    }
+
+    (antennas, [x_max, y_max])
+}
+
+// First line holds the size of the space along each axis, every other line an antenna like `A 1,2,3`
+fn parse_antenna_list<const D: usize>(input_string: &str) -> (Vec<Antenna<D>>, Point<D>) {
+    let parse_point = |coordinates: &str| -> Point<D> {
+        let values = coordinates.trim().split(',').map(|value| value.trim().parse().unwrap()).collect_vec();
+        values.try_into().unwrap_or_else(|_| panic!("Expected {} coordinates in {}", D, coordinates))
+    };
+
+    let mut lines = input_string.lines().filter(|line| !line.trim().is_empty());
+    let bounds = parse_point(lines.next().expect("Missing bounds"));
+    let antennas = lines
+        .map(|line| {
+            let (label, coordinates) = line.trim().split_once(' ').expect("Expected label and coordinates");
+            Antenna { position: parse_point(coordinates), label: label.chars().next().unwrap() }
+        })
+        .collect();
+
+    (antennas, bounds)
 }
 
 fn run_part_one(input_string: &str) -> usize {
-    simulate(input_string, false)
+    let (antennas, bounds) = parse_map(input_string);
+    find_antinodes(&antennas, bounds, &PART_ONE_CONFIG).len()
 }
 
 fn run_part_two(input_string: &str) -> usize {
-    simulate(input_string, true)
+    let (antennas, bounds) = parse_map(input_string);
+    find_antinodes(&antennas, bounds, &PART_TWO_CONFIG).len()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day08::{run_part_one, run_part_two};
+    use crate::day::day08::{antinodes_by_frequency, coinciding_antinodes, find_antinodes, frequency_stats, parse_antenna_list, parse_map, render_antinodes, FrequencyStats, PART_ONE_CONFIG, run_part_one, run_part_two, AntinodeConfig, PART_TWO_CONFIG};
     use crate::day::utils;
+    use std::collections::HashSet;
 
     fn example_input() -> String {
         String::from("\
@@ -121,4 +305,97 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(8)), 1235);
     }
+
+    #[test]
+    fn test_harmonic_ranges() {
+        let (antennas, bounds) = parse_map("\
+..........
+.A........
+..........
+..A.......
+..........
+..........
+..........
+..........
+..........
+..........");
+        let second_to_fourth = AntinodeConfig { first_harmonic: 2, last_harmonic: Some(4), reduce_step: false };
+        let antinodes = find_antinodes(&antennas, bounds, &second_to_fourth);
+        // (1, 1) -> (2, 3) steps by (1, 2), the other direction runs off the map right away
+        assert_eq!(antinodes.len(), 3);
+        assert!(antinodes.contains(&[3, 5]) && antinodes.contains(&[4, 7]) && antinodes.contains(&[5, 9]));
+
+        let (antennas, bounds) = parse_map("\
+.....
+A....
+.....
+.....
+.....
+....A");
+        let first_only = AntinodeConfig { first_harmonic: 1, last_harmonic: Some(1), reduce_step: true };
+        // Step (4, 4) reduces to (1, 1), so the first harmonic is right next to each antenna
+        assert_eq!(find_antinodes(&antennas, bounds, &first_only), HashSet::from([[1, 2], [3, 4]]));
+
+        let (antennas, bounds) = parse_map("A.A.A");
+        let reduced = AntinodeConfig { reduce_step: true, ..PART_TWO_CONFIG };
+        assert_eq!(find_antinodes(&antennas[..2], bounds, &PART_TWO_CONFIG).len(), 3);
+        assert_eq!(find_antinodes(&antennas[..2], bounds, &reduced).len(), 5);
+        assert_eq!(find_antinodes(&antennas, bounds, &reduced).len(), 5);
+    }
+
+    #[test]
+    fn test_3d_antennas() {
+        let (antennas, bounds) = parse_antenna_list::<3>("\
+10,10,10
+a 1,1,1
+a 2,3,4
+b 0,0,0");
+        let part_one = AntinodeConfig { first_harmonic: 2, last_harmonic: Some(2), reduce_step: false };
+        let antinodes = find_antinodes(&antennas, bounds, &part_one);
+        assert_eq!(antinodes, HashSet::from([[3, 5, 7]]));
+        assert_eq!(find_antinodes(&antennas, bounds, &PART_TWO_CONFIG), HashSet::from([[1, 1, 1], [2, 3, 4], [3, 5, 7]]));
+    }
+
+    #[test]
+    fn test_render_antinodes() {
+        let (antennas, bounds) = parse_map(&example_input());
+        let antinodes = antinodes_by_frequency(&antennas, bounds, &PART_ONE_CONFIG);
+        assert_eq!(render_antinodes(&antennas, bounds, &antinodes, None), "\
+......#....#
+...*....0...
+....#0....#.
+..#....0....
+....0....#..
+.#....A.....
+...#........
+#......#....
+........A...
+.........A..
+..........#.
+..........#.");
+        assert_eq!(render_antinodes(&antennas, bounds, &antinodes, Some('A')), "\
+............
+...#........
+....#.......
+............
+............
+......A.....
+............
+.......#....
+........A...
+.........A..
+..........#.
+..........#.");
+    }
+
+    #[test]
+    fn test_frequency_stats() {
+        let (antennas, bounds) = parse_map(&example_input());
+        let antinodes = antinodes_by_frequency(&antennas, bounds, &PART_ONE_CONFIG);
+        assert_eq!(coinciding_antinodes(&antinodes).into_iter().collect::<Vec<_>>(), vec![([3, 1], vec!['0', 'A'])]);
+        assert_eq!(frequency_stats(&antennas, &antinodes), vec![
+            FrequencyStats { frequency: '0', antennas: 4, antinodes: 10, shared_antinodes: 1 },
+            FrequencyStats { frequency: 'A', antennas: 3, antinodes: 5, shared_antinodes: 1 },
+        ]);
+    }
 }
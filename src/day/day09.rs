@@ -1,9 +1,26 @@
 use crate::day::utils;
+use itertools::Itertools;
+use std::collections::BTreeSet;
+use std::ops::Range;
 
 pub fn run() {
     let input_string = utils::read_input(9);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+
+    // Allocation strategies can be compared on the puzzle input with `cargo run -- 9 compare`, or only some
+    // of them with e.g. `cargo run -- 9 compare best-fit next-fit`
+    let args = std::env::args().skip(2).collect_vec();
+    if args.first().map(String::as_str) == Some("compare") {
+        let strategies = if args.len() == 1 {
+            ALL_STRATEGIES.to_vec()
+        } else {
+            args[1..].iter()
+                .map(|name| Strategy::from_name(name).unwrap_or_else(|| panic!("Unknown strategy {}", name)))
+                .collect()
+        };
+        print_comparison(&input_string, &strategies);
+    }
 }
 
 fn run_part_one(input_string: &str) -> usize {
@@ -67,86 +84,285 @@ fn parse_memory(input_string: &str) -> Vec<isize> {
     memory
 }
 
-fn run_part_two(input_string: &str) -> usize {
-    let mut memory = parse_memory(input_string);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileSpan {
+    id: usize,
+    start: usize,
+    length: usize,
+}
 
-    let mut end_index = memory.len() - 1;
-    let mut file_length;
-    let mut start_index;
-    let mut empty_space_length;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FreeSpan {
+    start: usize,
+    length: usize,
+}
 
-    loop {
-        start_index = 0;
+// Disk map kept as runs instead of single blocks
+struct DiskMap {
+    files: Vec<FileSpan>,
+    free: Vec<FreeSpan>,
+}
 
-        loop {
-            if memory[end_index] == -1 {
-                end_index -= 1;
-            } else {
-                break;
-            }
+fn parse_disk_map(input_string: &str) -> DiskMap {
+    let mut disk_map = DiskMap { files: vec![], free: vec![] };
+    let mut position = 0;
+    for (index, c) in input_string.trim().chars().enumerate() {
+        let length = c.to_digit(10).unwrap() as usize;
+        if index % 2 == 0 {
+            disk_map.files.push(FileSpan { id: index / 2, start: position, length });
+        } else if length > 0 {
+            disk_map.free.push(FreeSpan { start: position, length });
         }
-        // end index now first non-empty space elem at end
-        file_length = 1;
-        loop {
-            // determine moveable file len
-            if file_length <= end_index && memory[end_index - file_length] == memory[end_index] {
-                file_length += 1;
-            } else {
-                break;
-            }
+        position += length;
+    }
+    disk_map
+}
+
+// Free spans can never be longer than 9 blocks: a digit of the disk map, or what remains of one after a
+// file has been moved into it
+const MAX_SPAN_LENGTH: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    // Part one: single blocks from the end into the leftmost free block, splitting files up
+    Blocks,
+    // Part two: whole files into the leftmost span they fit in
+    FirstFit,
+    // Whole files into the smallest span they fit in, leftmost on ties
+    BestFit,
+    // Whole files into the largest span left of them, leftmost on ties
+    WorstFit,
+    // Like first fit, but searching on from where the previous file was put and wrapping around
+    NextFit,
+    // All files packed from the start of the disk in id order
+    Defragment,
+}
+
+const ALL_STRATEGIES: [Strategy; 6] = [
+    Strategy::Blocks, Strategy::FirstFit, Strategy::BestFit, Strategy::WorstFit, Strategy::NextFit, Strategy::Defragment,
+];
+
+impl Strategy {
+    fn name(&self) -> &'static str {
+        match self {
+            Strategy::Blocks => "blocks",
+            Strategy::FirstFit => "first-fit",
+            Strategy::BestFit => "best-fit",
+            Strategy::WorstFit => "worst-fit",
+            Strategy::NextFit => "next-fit",
+            Strategy::Defragment => "defragment",
         }
+    }
 
-        // find starting place that could fit the file len
-        loop {
-            loop {
-                if start_index < memory.len() && memory[start_index] != -1 {
-                    start_index += 1;
-                } else {
-                    break;
-                }
-            }
-            // start index now a free memory pos, check now many spaces are free
-            empty_space_length = 1;
-            loop {
-                if start_index + empty_space_length < memory.len() && memory[start_index + empty_space_length] == -1 {
-                    empty_space_length += 1
-                } else {
-                    break;
-                }
-            }
-            if empty_space_length >= file_length {
-                break;
-            } else if start_index >= memory.len() {
-                end_index -= file_length;
-                file_length = 0;
-                break;
-            } else {
-                start_index += empty_space_length;
+    fn from_name(name: &str) -> Option<Strategy> {
+        ALL_STRATEGIES.into_iter().find(|strategy| strategy.name() == name)
+    }
+}
+
+// Starts of the free spans, one ordered set per span length
+struct FreeSpans {
+    by_length: Vec<BTreeSet<usize>>,
+}
+
+impl FreeSpans {
+    fn new(spans: &[FreeSpan]) -> FreeSpans {
+        let mut by_length = vec![BTreeSet::new(); MAX_SPAN_LENGTH + 1];
+        for span in spans {
+            by_length[span.length].insert(span.start);
+        }
+        FreeSpans { by_length }
+    }
+
+    // First span of at least min_length starting in the given range, as (start, length)
+    fn first_in(&self, min_length: usize, range: Range<usize>) -> Option<(usize, usize)> {
+        (min_length.max(1)..=MAX_SPAN_LENGTH)
+            .filter_map(|length| {
+                let starts = &self.by_length[length];
+                // Most searches start at the beginning of the disk, where the smallest start does
+                let start = if range.start == 0 { starts.first() } else { starts.range(range.start..).next() };
+                start.filter(|&&start| start < range.end).map(|&start| (start, length))
+            })
+            .min()
+    }
+
+    // Picks a span for a file, only looking left of it
+    fn choose(&self, strategy: Strategy, file: &FileSpan, cursor: usize) -> Option<(usize, usize)> {
+        let first_of_length = |length: usize| self.by_length[length].first()
+            .filter(|&&start| start < file.start).map(|&start| (start, length));
+        match strategy {
+            Strategy::BestFit => (file.length..=MAX_SPAN_LENGTH).find_map(first_of_length),
+            Strategy::WorstFit => (file.length..=MAX_SPAN_LENGTH).rev().find_map(first_of_length),
+            Strategy::NextFit => self.first_in(file.length, cursor.min(file.start)..file.start)
+                .or_else(|| self.first_in(file.length, 0..cursor.min(file.start))),
+            _ => self.first_in(file.length, 0..file.start),
+        }
+    }
+
+    // Takes length blocks from the front of a span, keeping the rest free
+    fn allocate(&mut self, (start, span_length): (usize, usize), length: usize) {
+        self.by_length[span_length].remove(&start);
+        if span_length > length {
+            self.by_length[span_length - length].insert(start + length);
+        }
+    }
+}
+
+struct CompactionReport {
+    strategy: Strategy,
+    // Where the files ended up, a file split up by the block strategy has one entry per piece
+    fragments: Vec<FileSpan>,
+    moves: usize,
+    disk_length: usize,
+}
+
+impl CompactionReport {
+    fn checksum(&self) -> usize {
+        file_checksum(&self.fragments)
+    }
+
+    fn sorted_fragments(&self) -> Vec<FileSpan> {
+        let mut fragments = self.fragments.iter().filter(|fragment| fragment.length > 0).copied().collect_vec();
+        fragments.sort_by_key(|fragment| fragment.start);
+        fragments
+    }
+
+    // Number of contiguous pieces of each file, by id
+    fn fragments_per_file(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.fragments.iter().map(|fragment| fragment.id + 1).max().unwrap_or(0)];
+        let mut previous: Option<FileSpan> = None;
+        for fragment in self.sorted_fragments() {
+            let continues_previous = previous
+                .is_some_and(|previous| previous.id == fragment.id && previous.start + previous.length == fragment.start);
+            if !continues_previous {
+                counts[fragment.id] += 1;
             }
+            previous = Some(fragment);
         }
-        // found empty pos at start that fits end?
+        counts
+    }
 
-        // only swap if it isn't moved forward in the memory
-        if end_index < start_index {
-            if file_length > end_index {
-                break;
+    fn free_spans(&self) -> Vec<FreeSpan> {
+        let mut spans = vec![];
+        let mut position = 0;
+        for fragment in self.sorted_fragments() {
+            if fragment.start > position {
+                spans.push(FreeSpan { start: position, length: fragment.start - position });
             }
-            end_index -= file_length;
+            position = fragment.start + fragment.length;
+        }
+        if self.disk_length > position {
+            spans.push(FreeSpan { start: position, length: self.disk_length - position });
+        }
+        spans
+    }
+
+    fn largest_free_span(&self) -> usize {
+        self.free_spans().iter().map(|span| span.length).max().unwrap_or(0)
+    }
+}
+
+fn compact(disk_map: &DiskMap, strategy: Strategy) -> CompactionReport {
+    let disk_length = disk_map.files.iter().map(|file| file.start + file.length)
+        .chain(disk_map.free.iter().map(|span| span.start + span.length))
+        .max().unwrap_or(0);
+    let (fragments, moves) = match strategy {
+        Strategy::Blocks => compact_blocks(disk_map),
+        Strategy::Defragment => defragment(disk_map),
+        _ => compact_files(disk_map, strategy),
+    };
+    CompactionReport { strategy, fragments, moves, disk_length }
+}
+
+// Moves every file at most once, highest id first, into a span left of it picked by the strategy. Space freed
+// up by a moved file is never reused, as only files to the left of it are left to move.
+fn compact_files(disk_map: &DiskMap, strategy: Strategy) -> (Vec<FileSpan>, usize) {
+    let mut free_spans = FreeSpans::new(&disk_map.free);
+    let mut files = disk_map.files.clone();
+    let mut moves = 0;
+    let mut cursor = 0;
+    for file in files.iter_mut().rev() {
+        if file.length == 0 {
             continue;
         }
+        let Some(span) = free_spans.choose(strategy, file, cursor) else { continue };
+        free_spans.allocate(span, file.length);
+        file.start = span.0;
+        cursor = span.0 + file.length;
+        moves += 1;
+    }
+    (files, moves)
+}
 
-        // swap file length amount of blocks
-        for i in 0..file_length {
-            memory.swap(start_index + i, end_index - i);
+// Same result as part one, but moving as many blocks as fit in the leftmost span at once. Every such move
+// counts as one.
+fn compact_blocks(disk_map: &DiskMap) -> (Vec<FileSpan>, usize) {
+    let mut free_spans = FreeSpans::new(&disk_map.free);
+    let mut fragments = vec![];
+    let mut moves = 0;
+    for file in disk_map.files.iter().rev() {
+        let mut remaining = file.length;
+        while remaining > 0 {
+            let Some(span) = free_spans.first_in(1, 0..file.start) else { break };
+            let moved = remaining.min(span.1);
+            free_spans.allocate(span, moved);
+            fragments.push(FileSpan { id: file.id, start: span.0, length: moved });
+            remaining -= moved;
+            moves += 1;
         }
+        fragments.push(FileSpan { id: file.id, start: file.start, length: remaining });
     }
+    (fragments, moves)
+}
 
-    calculate_checksum(&mut memory)
+fn defragment(disk_map: &DiskMap) -> (Vec<FileSpan>, usize) {
+    let mut position = 0;
+    let mut moves = 0;
+    let files = disk_map.files.iter().map(|file| {
+        let moved = FileSpan { start: position, ..*file };
+        if file.length > 0 && moved.start != file.start {
+            moves += 1;
+        }
+        position += file.length;
+        moved
+    }).collect();
+    (files, moves)
+}
+
+fn compact_whole_files(disk_map: &DiskMap) -> Vec<FileSpan> {
+    compact(disk_map, Strategy::FirstFit).fragments
+}
+
+fn print_comparison(input_string: &str, strategies: &[Strategy]) {
+    let disk_map = parse_disk_map(input_string);
+    println!("{:<12}{:>18}{:>8}{:>12}{:>16}{:>14}", "strategy", "checksum", "moves", "fragmented", "max fragments", "largest free");
+    for &strategy in strategies {
+        let report = compact(&disk_map, strategy);
+        let fragments_per_file = report.fragments_per_file();
+        println!("{:<12}{:>18}{:>8}{:>12}{:>16}{:>14}",
+                 report.strategy.name(),
+                 report.checksum(),
+                 report.moves,
+                 fragments_per_file.iter().filter(|&&count| count > 1).count(),
+                 fragments_per_file.iter().max().unwrap_or(&0),
+                 report.largest_free_span());
+    }
+}
+
+fn file_checksum(files: &[FileSpan]) -> usize {
+    // Sum of start..start + length in closed form, times the id
+    files.iter()
+        .map(|file| file.id * (file.length * file.start + file.length * file.length.saturating_sub(1) / 2))
+        .sum()
+}
+
+fn run_part_two(input_string: &str) -> usize {
+    let disk_map = parse_disk_map(input_string);
+    file_checksum(&compact_whole_files(&disk_map))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day09::{run_part_one, run_part_two};
+    use crate::day::day09::{calculate_checksum, compact, compact_whole_files, CompactionReport, DiskMap, FileSpan, Strategy, ALL_STRATEGIES, file_checksum, parse_disk_map, parse_memory, run_part_one, run_part_two};
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -172,4 +388,124 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(9)), 6467290479134);
     }
+
+    // Straightforward block by block version of part two to check the span based one against
+    fn compact_whole_files_by_blocks(input_string: &str) -> usize {
+        let mut memory = parse_memory(input_string);
+        let max_id = *memory.iter().max().unwrap();
+        for id in (0..=max_id).rev() {
+            let file_start = memory.iter().position(|&block| block == id).unwrap();
+            let file_length = memory.iter().filter(|&&block| block == id).count();
+            let free_start = (0..file_start)
+                .find(|&start| start + file_length <= file_start && memory[start..start + file_length].iter().all(|&block| block == -1));
+            if let Some(free_start) = free_start {
+                for i in 0..file_length {
+                    memory.swap(free_start + i, file_start + i);
+                }
+            }
+        }
+        calculate_checksum(&mut memory)
+    }
+
+    fn pseudo_random_disk_map(length: usize, seed: u64) -> String {
+        let mut state = seed;
+        (0..length).map(|index| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let digit = (state >> 33) % 10;
+            // Files have at least one block
+            let digit = if index % 2 == 0 { digit.max(1) } else { digit };
+            char::from_digit(digit as u32, 10).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn test_compaction_matches_block_by_block() {
+        for seed in 0..5 {
+            let disk_map = pseudo_random_disk_map(1001, seed);
+            assert_eq!(run_part_two(&disk_map), compact_whole_files_by_blocks(&disk_map), "seed {}", seed);
+        }
+    }
+
+    // Also straightforward, but span by span so that it can keep up with larger disk maps: every file looks
+    // through all free spans from the left
+    fn compact_whole_files_by_scanning(disk_map: &DiskMap) -> usize {
+        let mut free = disk_map.free.clone();
+        let mut files = disk_map.files.clone();
+        for file in files.iter_mut().rev() {
+            let Some(span) = free.iter_mut()
+                .take_while(|span| span.start < file.start)
+                .find(|span| span.length >= file.length) else { continue };
+            file.start = span.start;
+            span.start += file.length;
+            span.length -= file.length;
+        }
+        file_checksum(&files)
+    }
+
+    #[test]
+    fn test_compaction_large_disk_map() {
+        let disk_map = pseudo_random_disk_map(60_001, 42);
+        let parsed = parse_disk_map(&disk_map);
+        let files = compact_whole_files(&parsed);
+        assert_eq!(files.len(), 30_001);
+        assert_eq!(file_checksum(&files), compact_whole_files_by_scanning(&parsed));
+    }
+
+    fn render(report: &CompactionReport) -> String {
+        let mut blocks = vec!['.'; report.disk_length];
+        for fragment in &report.fragments {
+            for block in &mut blocks[fragment.start..fragment.start + fragment.length] {
+                *block = char::from_digit(fragment.id as u32, 10).unwrap();
+            }
+        }
+        blocks.into_iter().collect()
+    }
+
+    #[test]
+    fn test_block_strategy_matches_part_one() {
+        let report = compact(&parse_disk_map(&example_input()), Strategy::Blocks);
+        assert_eq!(report.checksum(), 1928);
+        assert_eq!(compact(&parse_disk_map(&utils::read_input(9)), Strategy::Blocks).checksum(), 6432869891895);
+        // 0099811188827773336446555566..............
+        assert_eq!(report.fragments_per_file(), vec![1, 1, 1, 1, 1, 1, 3, 1, 2, 1]);
+        assert_eq!(report.largest_free_span(), 14);
+    }
+
+    #[test]
+    fn test_whole_file_strategies() {
+        let disk_map = parse_disk_map(&example_input());
+
+        // 00992111777.44.333....5555.6666.....8888..
+        let first_fit = compact(&disk_map, Strategy::FirstFit);
+        assert_eq!(first_fit.checksum(), 2858);
+        assert_eq!(first_fit.moves, 4);
+        assert_eq!(first_fit.largest_free_span(), 5);
+        assert!(first_fit.fragments_per_file().iter().all(|&count| count == 1));
+
+        assert_eq!(render(&compact(&parse_disk_map("1312211"), Strategy::FirstFit)), "03221......");
+        assert_eq!(render(&compact(&parse_disk_map("1312211"), Strategy::BestFit)), "01...22..3.");
+        assert_eq!(render(&compact(&parse_disk_map("11131"), Strategy::FirstFit)), "021....");
+        assert_eq!(render(&compact(&parse_disk_map("11131"), Strategy::WorstFit)), "01.2...");
+        assert_eq!(render(&compact(&parse_disk_map("111211112"), Strategy::FirstFit)), "031442.....");
+        assert_eq!(render(&compact(&parse_disk_map("111211112"), Strategy::NextFit)), "02144.3....");
+    }
+
+    #[test]
+    fn test_defragment_preserves_order() {
+        let report = compact(&parse_disk_map(&example_input()), Strategy::Defragment);
+        // 0011122333445555666677788889..............
+        assert_eq!(report.fragments.iter().map(|fragment| fragment.start).collect::<Vec<_>>(),
+                   vec![0, 2, 5, 6, 9, 11, 15, 19, 22, 26]);
+        assert_eq!(report.moves, 9);
+        assert_eq!(report.largest_free_span(), 14);
+        assert_eq!(report.fragments[9], FileSpan { id: 9, start: 26, length: 2 });
+    }
+
+    #[test]
+    fn test_strategy_names_round_trip() {
+        for strategy in ALL_STRATEGIES {
+            assert_eq!(Strategy::from_name(strategy.name()), Some(strategy));
+        }
+        assert_eq!(Strategy::from_name("random-fit"), None);
+    }
 }
@@ -1,13 +1,19 @@
 use crate::day::utils;
 use glam::{IVec2, U8Vec2};
 use itertools::Itertools;
-use std::collections::HashMap;
-use std::vec;
+use std::collections::BTreeMap;
 
 pub fn run() {
     let input_string = utils::read_input(10);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+
+    // Other step rules can be tried with e.g. `cargo run -- 10 any diagonal` or `cargo run -- 10 up-to=2`,
+    // `paths=n` additionally lists up to n trails per trail head
+    let args = std::env::args().skip(2).collect_vec();
+    if !args.is_empty() {
+        print_analysis(&input_string, &args);
+    }
 }
 
 static MOVEMENT_DELTAS: [IVec2; 4] = [
@@ -17,6 +23,31 @@ static MOVEMENT_DELTAS: [IVec2; 4] = [
     IVec2::new(-1, 0),
 ];
 
+static DIAGONAL_DELTAS: [IVec2; 4] = [
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, -1),
+    IVec2::new(-1, 1),
+];
+
+// Which moves a trail may make. Trails always have to climb, so no trail visits a cell twice and the
+// heights can be walked through from top to bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StepRule {
+    // None allows any increase
+    max_climb: Option<u8>,
+    diagonals: bool,
+}
+
+const PUZZLE_RULE: StepRule = StepRule { max_climb: Some(1), diagonals: false };
+
+impl StepRule {
+    fn deltas(&self) -> impl Iterator<Item = &IVec2> {
+        let diagonals: &[IVec2] = if self.diagonals { &DIAGONAL_DELTAS } else { &[] };
+        MOVEMENT_DELTAS.iter().chain(diagonals)
+    }
+}
+
 struct Trails {
     matrix: Vec<Vec<u8>>,
     trail_heads: Vec<U8Vec2>,
@@ -24,7 +55,7 @@ struct Trails {
 }
 
 impl Trails {
-    fn is_legal_move(&self, from: IVec2, delta: IVec2) -> bool {
+    fn is_legal_move(&self, from: IVec2, delta: IVec2, rule: &StepRule) -> bool {
         let target = from + delta;
         if target.x < 0 || target.y < 0 || target.y >= self.matrix.len() as i32 || target.x >= self.matrix[0].len() as i32  {
             return false;
@@ -32,11 +63,17 @@ impl Trails {
 
         let new_pos_topology = self.matrix[target.y as usize][target.x as usize];
         let current_post_topology = self.matrix[from.y as usize][from.x as usize];
-        if new_pos_topology <= current_post_topology || new_pos_topology - current_post_topology != 1 {
+        if new_pos_topology <= current_post_topology {
             return false;
         }
 
-        true
+        rule.max_climb.is_none_or(|max_climb| new_pos_topology - current_post_topology <= max_climb)
+    }
+
+    fn legal_moves<'a>(&'a self, from: U8Vec2, rule: &'a StepRule) -> impl Iterator<Item = U8Vec2> + 'a {
+        rule.deltas()
+            .filter(move |delta| self.is_legal_move(from.as_ivec2(), **delta, rule))
+            .map(move |delta| (from.as_ivec2() + *delta).as_u8vec2())
     }
 }
 
@@ -63,59 +100,148 @@ fn parse_input(input_string: &str) -> Trails {
     Trails {matrix, trail_heads, peaks}
 }
 
+// Number of trails from every cell to every peak it can reach, keyed by index into `trails.peaks`
+type PeakCounts = Vec<Vec<BTreeMap<usize, u64>>>;
+
+struct TrailAnalysis {
+    // One row per trail head and one column per peak, in the order of `Trails`
+    path_counts: Vec<Vec<u64>>,
+    // The counts for every cell they were taken from, which also guide `enumerate_paths`
+    counts: PeakCounts,
+}
+
+impl TrailAnalysis {
+    fn scores(&self) -> Vec<usize> {
+        self.path_counts.iter().map(|row| row.iter().filter(|&&count| count > 0).count()).collect()
+    }
+
+    fn ratings(&self) -> Vec<u64> {
+        self.path_counts.iter().map(|row| row.iter().sum()).collect()
+    }
+}
+
+// Fills in the trail counts from the peaks downwards, each cell summing up the cells it can climb to
+fn count_paths_to_peaks(trails: &Trails, rule: &StepRule) -> PeakCounts {
+    let mut counts: PeakCounts = trails.matrix.iter().map(|row| vec![BTreeMap::new(); row.len()]).collect();
+    for (index, peak) in trails.peaks.iter().enumerate() {
+        counts[peak.y as usize][peak.x as usize].insert(index, 1);
+    }
+
+    let cells = trails.matrix.iter().enumerate()
+        .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, &height)| (height, U8Vec2::new(x as u8, y as u8))))
+        .filter(|&(height, _)| height < 9)
+        .sorted_by(|a, b| b.0.cmp(&a.0))
+        .collect_vec();
+
+    for (_, cell) in cells {
+        let mut cell_counts = BTreeMap::new();
+        for next in trails.legal_moves(cell, rule) {
+            for (&peak, &count) in &counts[next.y as usize][next.x as usize] {
+                *cell_counts.entry(peak).or_insert(0) += count;
+            }
+        }
+        counts[cell.y as usize][cell.x as usize] = cell_counts;
+    }
+
+    counts
+}
+
+fn analyse_trails(trails: &Trails, rule: &StepRule) -> TrailAnalysis {
+    let counts = count_paths_to_peaks(trails, rule);
+    let path_counts = trails.trail_heads.iter()
+        .map(|head| {
+            let head_counts = &counts[head.y as usize][head.x as usize];
+            (0..trails.peaks.len()).map(|peak| head_counts.get(&peak).copied().unwrap_or(0)).collect()
+        })
+        .collect();
+    TrailAnalysis { path_counts, counts }
+}
+
+// Lists the trails starting at a trail head, stopping after `limit` of them. Cells that can't reach a peak are
+// skipped using the counts of an analysis under the same rule, so every path followed ends up as a trail.
+fn enumerate_paths(trails: &Trails, analysis: &TrailAnalysis, trail_head: U8Vec2, rule: &StepRule, limit: usize) -> Vec<Vec<U8Vec2>> {
+    let mut paths = Vec::new();
+    find_distinct_paths(trails, rule, &analysis.counts, &mut vec![trail_head], &mut paths, limit);
+    paths
+}
+
 fn find_distinct_paths(trails: &Trails,
-                       current_pos: U8Vec2,
+                       rule: &StepRule,
+                       counts: &PeakCounts,
                        current_path: &mut Vec<U8Vec2>,
-                       reachable_peaks: &mut HashMap<U8Vec2, usize>)  {
-
-    if trails.peaks.contains(&current_pos) {
-        *reachable_peaks.entry(current_pos).or_insert(0) += 1;
+                       paths: &mut Vec<Vec<U8Vec2>>,
+                       limit: usize) {
+    let current_pos = *current_path.last().unwrap();
+    if trails.matrix[current_pos.y as usize][current_pos.x as usize] == 9 {
+        paths.push(current_path.clone());
         return;
     }
 
-    for delta in MOVEMENT_DELTAS.iter() {
-        if !trails.is_legal_move(current_pos.as_ivec2(), *delta) {
+    for new_pos in trails.legal_moves(current_pos, rule) {
+        if paths.len() >= limit {
+            return;
+        }
+        if counts[new_pos.y as usize][new_pos.x as usize].is_empty() {
             continue;
         }
 
-        let new_pos = (current_pos.as_ivec2() + *delta).as_u8vec2();
         current_path.push(new_pos);
-        find_distinct_paths(trails, new_pos, current_path, reachable_peaks);
+        find_distinct_paths(trails, rule, counts, current_path, paths, limit);
         current_path.pop();
     }
 }
 
-fn run_part_one(input_string: &str) -> usize {
-    let trails  = parse_input(input_string);
-    let mut trail_score_sum = 0;
+fn parse_rule(args: &[String]) -> StepRule {
+    let mut rule = PUZZLE_RULE;
+    for arg in args {
+        if arg == "any" {
+            rule.max_climb = None;
+        } else if arg == "diagonal" {
+            rule.diagonals = true;
+        } else if let Some(max_climb) = arg.strip_prefix("up-to=") {
+            rule.max_climb = Some(max_climb.parse().expect("Maximum climb has to be a number"));
+        } else if !arg.starts_with("paths=") {
+            panic!("Unknown step rule {}", arg);
+        }
+    }
+    rule
+}
 
-    for trail_head in &trails.trail_heads {
-        let mut reachable_peaks = HashMap::new();
-        find_distinct_paths(&trails, *trail_head, &mut vec![*trail_head], &mut reachable_peaks);
+fn print_analysis(input_string: &str, args: &[String]) {
+    let trails = parse_input(input_string);
+    let rule = parse_rule(args);
+    let analysis = analyse_trails(&trails, &rule);
+    let (scores, ratings) = (analysis.scores(), analysis.ratings());
+    println!("{}", scores.iter().sum::<usize>());
+    println!("{}", ratings.iter().sum::<u64>());
 
-        trail_score_sum += reachable_peaks.len();
+    let limit = args.iter().find_map(|arg| arg.strip_prefix("paths="))
+        .map(|limit| limit.parse::<usize>().expect("Path limit has to be a number"));
+    if let Some(limit) = limit {
+        for (index, trail_head) in trails.trail_heads.iter().enumerate() {
+            println!("trail head {},{}: score {}, rating {}",
+                     trail_head.x, trail_head.y, scores[index], ratings[index]);
+            for path in enumerate_paths(&trails, &analysis, *trail_head, &rule, limit) {
+                println!("  {}", path.iter().map(|cell| format!("{},{}", cell.x, cell.y)).join(" -> "));
+            }
+        }
     }
-
-    trail_score_sum
 }
 
-fn run_part_two(input_string: &str) -> usize {
+fn run_part_one(input_string: &str) -> usize {
     let trails  = parse_input(input_string);
-    let mut trail_rating_sum = 0;
-
-    for trail_head in &trails.trail_heads {
-        let mut reachable_peaks = HashMap::new();
-        find_distinct_paths(&trails, *trail_head, &mut vec![*trail_head], &mut reachable_peaks);
-
-        trail_rating_sum += reachable_peaks.iter().map(|x| x.1).sum::<usize>();
-    }
+    analyse_trails(&trails, &PUZZLE_RULE).scores().iter().sum()
+}
 
-    trail_rating_sum
+fn run_part_two(input_string: &str) -> u64 {
+    let trails  = parse_input(input_string);
+    analyse_trails(&trails, &PUZZLE_RULE).ratings().iter().sum()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day10::{run_part_one, run_part_two};
+    use crate::day::day10::{analyse_trails, enumerate_paths, parse_input, run_part_one, run_part_two, StepRule, PUZZLE_RULE};
+    use glam::U8Vec2;
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -150,4 +276,46 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(10)), 1255);
     }
+
+    #[test]
+    fn test_analysis_matrix() {
+        let trails = parse_input(&example_input());
+        let analysis = analyse_trails(&trails, &PUZZLE_RULE);
+        assert_eq!(analysis.path_counts.len(), 9);
+        assert_eq!(analysis.path_counts[0].len(), 7);
+        assert_eq!(analysis.scores(), vec![5, 6, 5, 3, 1, 3, 5, 3, 5]);
+        assert_eq!(analysis.ratings(), vec![20, 24, 10, 4, 1, 4, 5, 8, 5]);
+    }
+
+    #[test]
+    fn test_other_step_rules() {
+        let input = "\
+0123456
+1234567
+2345678
+3456789";
+        let trails = parse_input(input);
+        let rating = |max_climb, diagonals| analyse_trails(&trails, &StepRule { max_climb, diagonals }).ratings()[0];
+        // Right and down steps only, picking 3 downward steps out of 9
+        assert_eq!(rating(Some(1), false), 84);
+        // Diagonal steps climb by 0 or 2
+        assert_eq!(rating(Some(1), true), 84);
+        assert_eq!(rating(None, false), 84);
+        // Down-right diagonals as well, the Delannoy number D(6, 3)
+        assert_eq!(rating(Some(2), true), 377);
+        assert_eq!(rating(None, true), 377);
+    }
+
+    #[test]
+    fn test_enumerate_paths() {
+        let trails = parse_input(&example_input());
+        let trail_head = trails.trail_heads[0];
+        let analysis = analyse_trails(&trails, &PUZZLE_RULE);
+        let paths = enumerate_paths(&trails, &analysis, trail_head, &PUZZLE_RULE, 100);
+        assert_eq!(paths.len(), 20);
+        assert!(paths.iter().all(|path| path.len() == 10 && path[0] == trail_head));
+        assert_eq!(paths.len() as u64, analysis.ratings()[0]);
+        assert_eq!(enumerate_paths(&trails, &analysis, trail_head, &PUZZLE_RULE, 3).len(), 3);
+        assert_eq!(trail_head, U8Vec2::new(2, 0));
+    }
 }
@@ -1,11 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use itertools::Itertools;
+use num_bigint::BigUint;
+use num_traits::{CheckedAdd, One, Zero};
 use crate::day::utils;
 
 pub fn run() {
     let input_string = utils::read_input(11);
     println!("{}", run_part_one(&input_string, 25));
     println!("{}", run_part_two(&input_string, 75));
+
+    // Any number of blinks can be counted with e.g. `cargo run -- 11 blinks=1000`, or modulo some number with
+    // `cargo run -- 11 blinks=1000000 mod=1000000007`. `rules=file` swaps in other stone rules (see
+    // `RuleSet::parse`), `histogram` lists how many stones have each value instead.
+    let args = std::env::args().skip(2).collect_vec();
+    if !args.is_empty() {
+        print_count(&input_string, &args);
+    }
 }
 
 fn run_part_one(input_string: &str, blinks: usize) -> usize {
@@ -43,48 +53,430 @@ fn run_part_one(input_string: &str, blinks: usize) -> usize {
     stones.len()
 }
 
-fn run_part_two(input_string: &str, blinks: usize) -> usize {
-    // Memoization map storing (elem, blinks) -> (resulting stones count)
-    let mut dp_map = HashMap::new();
+fn run_part_two(input_string: &str, blinks: usize) -> u128 {
+    let stones = parse_stones(input_string);
+    TransitionMatrix::new(&stones, &RuleSet::puzzle()).unwrap().count_exact::<u128>(&stones, blinks)
+}
 
+fn parse_stones(input_string: &str) -> Vec<usize> {
     input_string.split_whitespace()
         .map(|s| s.parse::<usize>().unwrap())
-        .map(|stone| find_stone_count(stone, blinks, &mut dp_map))
-        .sum()
+        .collect_vec()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Predicate {
+    Equals(usize),
+    // Number of digits divisible by this, in the base of the rule set
+    DigitsMultipleOf(u32),
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transformation {
+    Replace(usize),
+    // Cuts the digits into this many equally long stones. Only allowed where the predicate guarantees a digit
+    // count divisible by this, see `RuleSet::parse`.
+    Split(u32),
+    Multiply(usize),
+    Add(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rule {
+    predicate: Predicate,
+    transformation: Transformation,
+}
+
+// The first matching rule changes a stone, stones no rule matches stay as they are
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RuleSet {
+    base: usize,
+    rules: Vec<Rule>,
+}
+
+const PUZZLE_RULES: &str = "\
+base 10
+= 0 -> 1
+digits % 2 -> split 2
+any -> * 2024";
+
+impl RuleSet {
+    // One rule per line as `predicate -> transformation`, optionally preceded by a `base n` line. Predicates
+    // are `= n`, `digits % n` and `any`, transformations `n`, `split n`, `* n` and `+ n`. Everything after a
+    // `#` is ignored.
+    fn parse(config: &str) -> Result<RuleSet, String> {
+        let mut rule_set = RuleSet { base: 10, rules: vec![] };
+        for line in config.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(base) = line.strip_prefix("base ") {
+                rule_set.base = parse_number(base)?;
+                if rule_set.base < 2 {
+                    return Err(format!("Base has to be at least 2, got {}", rule_set.base));
+                }
+                continue;
+            }
+
+            let (predicate, transformation) = line.split_once("->")
+                .ok_or_else(|| format!("Rule {:?} is missing a ->", line))?;
+            let predicate = match predicate.trim() {
+                "any" => Predicate::Any,
+                predicate => if let Some(value) = predicate.strip_prefix('=') {
+                    Predicate::Equals(parse_number(value)?)
+                } else if let Some(divisor) = predicate.strip_prefix("digits").and_then(|rest| rest.trim().strip_prefix('%')) {
+                    Predicate::DigitsMultipleOf(parse_number(divisor)?)
+                } else {
+                    return Err(format!("Unknown predicate {:?}", predicate));
+                }
+            };
+            let transformation = transformation.trim();
+            let transformation = if let Some(parts) = transformation.strip_prefix("split") {
+                Transformation::Split(parse_number(parts)?)
+            } else if let Some(factor) = transformation.strip_prefix('*') {
+                Transformation::Multiply(parse_number(factor)?)
+            } else if let Some(term) = transformation.strip_prefix('+') {
+                Transformation::Add(parse_number(term)?)
+            } else {
+                Transformation::Replace(parse_number(transformation)?)
+            };
+            if transformation == Transformation::Split(0) {
+                return Err(String::from("Stones can't be split into 0 parts"));
+            }
+            rule_set.rules.push(Rule { predicate, transformation });
+        }
+
+        // Only known once the base is
+        if let Some(rule) = rule_set.rules.iter().find(|rule| !rule_set.splits_evenly(rule)) {
+            return Err(format!("{:?} can't cut every stone it matches into equally long parts", rule));
+        }
+        Ok(rule_set)
+    }
+
+    fn splits_evenly(&self, rule: &Rule) -> bool {
+        let Transformation::Split(parts) = rule.transformation else { return true };
+        match rule.predicate {
+            Predicate::Equals(value) => self.digits_count(value).is_multiple_of(parts),
+            Predicate::DigitsMultipleOf(divisor) => divisor.is_multiple_of(parts),
+            Predicate::Any => false,
+        }
+    }
+
+    fn puzzle() -> RuleSet {
+        RuleSet::parse(PUZZLE_RULES).unwrap()
+    }
+
+    fn digits_count(&self, stone: usize) -> u32 {
+        let mut digits_count = 1;
+        let mut rest = stone / self.base;
+        while rest > 0 {
+            digits_count += 1;
+            rest /= self.base;
+        }
+        digits_count
+    }
+
+    fn matches(&self, predicate: Predicate, stone: usize) -> bool {
+        match predicate {
+            Predicate::Equals(value) => stone == value,
+            Predicate::DigitsMultipleOf(divisor) => self.digits_count(stone).is_multiple_of(divisor),
+            Predicate::Any => true,
+        }
+    }
+
+    // Errors once a stone outgrows usize, rule sets that multiply or add without ever splitting get there
+    fn blink(&self, stone: usize) -> Result<Vec<usize>, String> {
+        let Some(rule) = self.rules.iter().find(|rule| self.matches(rule.predicate, stone)) else {
+            return Ok(vec![stone]);
+        };
+        let grown = match rule.transformation {
+            Transformation::Replace(value) => Some(value),
+            Transformation::Split(parts) => {
+                let part_size = self.base.pow(self.digits_count(stone) / parts);
+                let mut stones = (0..parts)
+                    .scan(stone, |rest, _| {
+                        let part = *rest % part_size;
+                        *rest /= part_size;
+                        Some(part)
+                    })
+                    .collect_vec();
+                stones.reverse();
+                return Ok(stones);
+            }
+            Transformation::Multiply(factor) => stone.checked_mul(factor),
+            Transformation::Add(term) => stone.checked_add(term),
+        };
+        grown.map(|value| vec![value]).ok_or(format!("Stone {} grows beyond {}", stone, usize::MAX))
+    }
 }
 
-fn find_stone_count(elem: usize, iterations: usize, dp_map: &mut HashMap<(usize, usize), usize>) -> usize {
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("Expected a number, got {:?}", value.trim()))
+}
+
+// Counts stones one starting stone at a time, remembering the count for every (stone, blinks) pair seen.
+// Optionally modulo some number, see `parse_modulus`.
+fn count_memoised(rules: &RuleSet, stones: &[usize], blinks: usize, modulus: Option<u64>) -> Result<u128, String> {
+    let mut dp_map = HashMap::new();
+    stones.iter().try_fold(0, |total, &stone| {
+        let count = find_stone_count(rules, stone, blinks, modulus, &mut dp_map)?;
+        add_counts(total, count, modulus)
+    })
+}
+
+fn find_stone_count(rules: &RuleSet, elem: usize, iterations: usize, modulus: Option<u64>, dp_map: &mut HashMap<(usize, usize), u128>) -> Result<u128, String> {
     if iterations == 0 {
-        return 1;
+        return Ok(modulus.map_or(1, |modulus| 1 % modulus as u128));
     }
 
     if let Some(result) = dp_map.get(&(elem, iterations)) {
-        return *result;
-    }
-
-    let result;
-    if elem == 0 {
-        result = find_stone_count(1, iterations - 1, dp_map);
-    } else {
-        let digits_count = (elem as f64).log10().floor() as usize + 1;
-        if digits_count % 2 == 0 {
-            let split = 10_usize.pow((digits_count / 2) as u32);
-            let first = elem / split;
-            let second = elem % split;
-            result = find_stone_count(first, iterations - 1, dp_map)
-                + find_stone_count(second, iterations - 1, dp_map);
+        return Ok(*result);
+    }
+
+    let result = rules.blink(elem)?.into_iter().try_fold(0, |total, stone| {
+        let count = find_stone_count(rules, stone, iterations - 1, modulus, dp_map)?;
+        add_counts(total, count, modulus)
+    })?;
+    dp_map.insert((elem, iterations), result);
+    Ok(result)
+}
+
+fn add_counts(left: u128, right: u128, modulus: Option<u64>) -> Result<u128, String> {
+    match modulus {
+        Some(modulus) => Ok((left + right) % modulus as u128),
+        None => left.checked_add(right).ok_or("Stone count overflowed, count modulo some number instead".to_string()),
+    }
+}
+
+// How many stones carry each value after the given number of blinks
+fn value_histogram(rules: &RuleSet, stones: &[usize], blinks: usize) -> Result<BTreeMap<usize, u128>, String> {
+    let mut histogram = BTreeMap::new();
+    for &stone in stones {
+        *histogram.entry(stone).or_insert(0) += 1;
+    }
+    for _ in 0..blinks {
+        let mut next_histogram = BTreeMap::new();
+        for (stone, count) in histogram {
+            for next in rules.blink(stone)? {
+                let total: &mut u128 = next_histogram.entry(next).or_insert(0);
+                *total = add_counts(*total, count, None)?;
+            }
+        }
+        histogram = next_histogram;
+    }
+    Ok(histogram)
+}
+
+const MAX_CLOSED_VALUES: usize = 1 << 20;
+
+// Stones only ever take a few thousand different values, so a blink is a linear map on the number of stones
+// per value. Row i lists the values stone i turns into.
+struct TransitionMatrix {
+    values: Vec<usize>,
+    index: HashMap<usize, usize>,
+    successors: Vec<Vec<usize>>,
+}
+
+impl TransitionMatrix {
+    // Closes the starting stones under blinking. Not every rule set keeps the stones to a finite set of values,
+    // so this gives up after MAX_CLOSED_VALUES, or once a stone outgrows usize.
+    fn new(stones: &[usize], rules: &RuleSet) -> Option<TransitionMatrix> {
+        let mut matrix = TransitionMatrix { values: vec![], index: HashMap::new(), successors: vec![] };
+        let mut pending = stones.to_vec();
+        while let Some(stone) = pending.pop() {
+            if matrix.index.contains_key(&stone) {
+                continue;
+            }
+            if matrix.values.len() == MAX_CLOSED_VALUES {
+                return None;
+            }
+            matrix.index.insert(stone, matrix.values.len());
+            matrix.values.push(stone);
+            pending.extend(rules.blink(stone).ok()?);
+        }
+        matrix.successors = matrix.values.iter()
+            .map(|&stone| rules.blink(stone).unwrap().iter().map(|next| matrix.index[next]).collect())
+            .collect();
+        Some(matrix)
+    }
+
+    fn dimension(&self) -> usize {
+        self.values.len()
+    }
+
+    fn initial_counts<C: Zero + One + CheckedAdd + Clone>(&self, stones: &[usize]) -> Vec<C> {
+        let mut counts = vec![C::zero(); self.dimension()];
+        for stone in stones {
+            let count = &mut counts[self.index[stone]];
+            *count = count.checked_add(&C::one()).unwrap();
+        }
+        counts
+    }
+
+    fn blink_counts<C: Zero + CheckedAdd + Clone>(&self, counts: &[C]) -> Vec<C> {
+        let mut next_counts = vec![C::zero(); self.dimension()];
+        for (count, successors) in counts.iter().zip(&self.successors) {
+            if count.is_zero() {
+                continue;
+            }
+            for &successor in successors {
+                next_counts[successor] = next_counts[successor].checked_add(count)
+                    .expect("Stone count overflowed, use a bigger count type");
+            }
+        }
+        next_counts
+    }
+
+    // Blinks one step at a time, works with u128 or BigUint totals
+    fn count_exact<C: Zero + One + CheckedAdd + Clone>(&self, stones: &[usize], blinks: usize) -> C {
+        let mut counts = self.initial_counts::<C>(stones);
+        for _ in 0..blinks {
+            counts = self.blink_counts(&counts);
+        }
+        counts.into_iter().fold(C::zero(), |total, count| total.checked_add(&count).unwrap())
+    }
+
+    fn dense_mod(&self, modulus: u64) -> Vec<Vec<u64>> {
+        let mut dense = vec![vec![0; self.dimension()]; self.dimension()];
+        for (row, successors) in dense.iter_mut().zip(&self.successors) {
+            for &successor in successors {
+                row[successor] = (row[successor] + 1) % modulus;
+            }
+        }
+        dense
+    }
+
+    // Number of stones modulo `modulus` by square and multiply on the dense matrix. Only worth it for small
+    // closed sets, the puzzle input closes over about 4000 values.
+    fn count_by_power_mod(&self, stones: &[usize], blinks: usize, modulus: u64) -> u64 {
+        let mut counts = self.initial_counts::<u64>(stones).into_iter().map(|count| count % modulus).collect_vec();
+        let mut power = self.dense_mod(modulus);
+        let mut remaining = blinks;
+        while remaining > 0 {
+            if remaining % 2 == 1 {
+                counts = multiply_vector_mod(&counts, &power, modulus);
+            }
+            remaining /= 2;
+            if remaining > 0 {
+                power = multiply_matrices_mod(&power, &power, modulus);
+            }
+        }
+        counts.iter().fold(0, |total, count| (total + count) % modulus)
+    }
+
+    fn count_by_blinking_mod(&self, stones: &[usize], blinks: usize, modulus: u64) -> u64 {
+        let mut counts = self.initial_counts::<u64>(stones).into_iter().map(|count| count % modulus).collect_vec();
+        // One flat list of transitions is quicker to go through than the rows, which adds up over millions of blinks
+        let transitions = self.successors.iter().enumerate()
+            .flat_map(|(stone, successors)| successors.iter().map(move |&successor| (stone as u32, successor as u32)))
+            .collect_vec();
+        let mut next_counts = vec![0; self.dimension()];
+        for _ in 0..blinks {
+            next_counts.fill(0);
+            for &(stone, successor) in &transitions {
+                // Both are already reduced, so one subtraction is enough
+                let sum = next_counts[successor as usize] + counts[stone as usize];
+                next_counts[successor as usize] = if sum >= modulus { sum - modulus } else { sum };
+            }
+            std::mem::swap(&mut counts, &mut next_counts);
+        }
+        counts.iter().fold(0, |total, count| (total + count) % modulus)
+    }
+
+    // Picks whichever of the two is cheaper for this many blinks, see `parse_modulus` for which moduli work.
+    // Dense matrices of the puzzle input's closed set would take far too much memory, so the square and multiply
+    // is only ever used for small ones.
+    fn count_mod(&self, stones: &[usize], blinks: usize, modulus: u64) -> u64 {
+        let dimension = self.dimension() as u128;
+        let transitions = self.successors.iter().map(Vec::len).sum::<usize>() as u128;
+        let squarings = (usize::BITS - blinks.leading_zeros()) as u128;
+        if self.dimension() <= MAX_DENSE_DIMENSION && dimension * dimension * dimension * squarings < transitions * blinks as u128 {
+            self.count_by_power_mod(stones, blinks, modulus)
         } else {
-            result = find_stone_count(elem * 2024, iterations - 1, dp_map);
+            self.count_by_blinking_mod(stones, blinks, modulus)
         }
     }
+}
 
-    dp_map.insert((elem, iterations), result);
+const MAX_DENSE_DIMENSION: usize = 512;
+
+// Counting modulo 0 makes no sense, and the modulus has to stay below 2^63 so that two residues can be added up
+// without overflowing
+fn parse_modulus(value: &str) -> Result<u64, String> {
+    let modulus: u64 = parse_number(value)?;
+    if modulus == 0 || modulus >= 1 << 63 {
+        return Err(format!("Modulus has to be between 1 and 2^63 - 1, got {}", modulus));
+    }
+    Ok(modulus)
+}
+
+fn multiply_vector_mod(vector: &[u64], matrix: &[Vec<u64>], modulus: u64) -> Vec<u64> {
+    let mut result = vec![0; vector.len()];
+    for (&value, row) in vector.iter().zip(matrix) {
+        if value == 0 {
+            continue;
+        }
+        for (target, &entry) in result.iter_mut().zip(row) {
+            *target = ((*target as u128 + value as u128 * entry as u128) % modulus as u128) as u64;
+        }
+    }
     result
 }
 
+fn multiply_matrices_mod(left: &[Vec<u64>], right: &[Vec<u64>], modulus: u64) -> Vec<Vec<u64>> {
+    left.iter().map(|row| multiply_vector_mod(row, right, modulus)).collect()
+}
+
+fn print_count(input_string: &str, args: &[String]) {
+    let stones = parse_stones(input_string);
+    let mut rules = RuleSet::puzzle();
+    let mut blinks = 75;
+    let mut modulus = None;
+    let mut histogram = false;
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("blinks=") {
+            blinks = value.parse().expect("Blinks have to be a number");
+        } else if let Some(value) = arg.strip_prefix("mod=") {
+            modulus = Some(parse_modulus(value).unwrap_or_else(|error| panic!("{}", error)));
+        } else if let Some(file) = arg.strip_prefix("rules=") {
+            let config = std::fs::read_to_string(file).expect("Failed to read rules file");
+            rules = RuleSet::parse(&config).unwrap_or_else(|error| panic!("Invalid rules: {}", error));
+        } else if arg == "histogram" {
+            histogram = true;
+        } else {
+            panic!("Unknown option {}", arg);
+        }
+    }
+
+    if histogram {
+        match value_histogram(&rules, &stones, blinks) {
+            Ok(histogram) => histogram.iter().for_each(|(stone, count)| println!("{:>20} {}", stone, count)),
+            Err(error) => println!("{}", error),
+        }
+        return;
+    }
+
+    let Some(matrix) = TransitionMatrix::new(&stones, &rules) else {
+        println!("Stone values don't close up, counting blink by blink");
+        match count_memoised(&rules, &stones, blinks, modulus) {
+            Ok(count) => println!("{}", count),
+            Err(error) => println!("{}", error),
+        }
+        return;
+    };
+    println!("{} stone values", matrix.dimension());
+    match modulus {
+        Some(modulus) => println!("{}", matrix.count_mod(&stones, blinks, modulus)),
+        None => println!("{}", matrix.count_exact::<BigUint>(&stones, blinks)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::day::day11::{run_part_one, run_part_two};
+    use crate::day::day11::{count_memoised, parse_modulus, parse_stones, run_part_one, run_part_two, value_histogram, RuleSet, TransitionMatrix};
+    use num_bigint::BigUint;
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -108,4 +500,154 @@ mod tests {
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(11), 75), 241651071960597);
     }
+
+    #[test]
+    fn test_counts_past_usize() {
+        let stones = vec![125, 17];
+        let matrix = TransitionMatrix::new(&stones, &RuleSet::puzzle()).unwrap();
+        assert_eq!(matrix.count_exact::<u128>(&stones, 25), 55312);
+
+        // Somewhere past 150 blinks the count no longer fits in 64 bits
+        let exact = matrix.count_exact::<BigUint>(&stones, 300);
+        assert!(exact.bits() > 128);
+        let modulus = 1_000_000_007;
+        assert_eq!(matrix.count_mod(&stones, 300, modulus), (exact % modulus).iter_u64_digits().next().unwrap_or(0));
+    }
+
+    #[test]
+    fn test_power_agrees_with_blinking() {
+        // 0 only ever turns into 54 different values
+        let stones = vec![0];
+        let matrix = TransitionMatrix::new(&stones, &RuleSet::puzzle()).unwrap();
+        assert_eq!(matrix.dimension(), 54);
+        let modulus = 998_244_353;
+        for blinks in [0, 1, 6, 75, 1000] {
+            assert_eq!(matrix.count_by_power_mod(&stones, blinks, modulus),
+                       matrix.count_by_blinking_mod(&stones, blinks, modulus));
+        }
+    }
+
+    #[test]
+    fn test_huge_blink_count() {
+        let stones = vec![0];
+        let matrix = TransitionMatrix::new(&stones, &RuleSet::puzzle()).unwrap();
+        let modulus = 1_000_000_007;
+        let count = matrix.count_mod(&stones, 1_000_000_000_000, modulus);
+        assert!(count < modulus);
+        // Same number both ways round
+        assert_eq!(matrix.count_mod(&stones, 100_000, modulus), matrix.count_by_blinking_mod(&stones, 100_000, modulus));
+    }
+
+    #[test]
+    fn test_modulus_validation() {
+        assert_eq!(parse_modulus("1000000007"), Ok(1_000_000_007));
+        assert_eq!(parse_modulus("1"), Ok(1));
+        assert!(parse_modulus("0").is_err());
+        assert!(parse_modulus("9223372036854775808").is_err());
+        assert_eq!(parse_modulus("9223372036854775807"), Ok((1 << 63) - 1));
+        assert!(parse_modulus("-5").is_err());
+    }
+
+    #[test]
+    fn test_large_closed_sets_count_by_blinking() {
+        // The input closes over thousands of values, far more than a dense matrix should be built for
+        let stones = parse_stones(&utils::read_input(11));
+        let matrix = TransitionMatrix::new(&stones, &RuleSet::puzzle()).unwrap();
+        assert!(matrix.dimension() > 512);
+        let modulus = 1_000_000_007;
+        let exact = matrix.count_exact::<BigUint>(&stones, 500);
+        assert_eq!(matrix.count_mod(&stones, 500, modulus), (exact % modulus).iter_u64_digits().next().unwrap_or(0));
+    }
+
+    #[test]
+    fn test_puzzle_rules_from_config() {
+        let rules = RuleSet::puzzle();
+        assert_eq!(rules.blink(0), Ok(vec![1]));
+        assert_eq!(rules.blink(1000), Ok(vec![10, 0]));
+        assert_eq!(rules.blink(99), Ok(vec![9, 9]));
+        assert_eq!(rules.blink(125), Ok(vec![253000]));
+        assert_eq!(count_memoised(&rules, &[125, 17], 25, None), Ok(55312));
+        assert_eq!(count_memoised(&rules, &[125, 17], 75, None), Ok(TransitionMatrix::new(&[125, 17], &rules).unwrap().count_exact::<u128>(&[125, 17], 75)));
+    }
+
+    #[test]
+    fn test_other_rules() {
+        let rules = RuleSet::parse("\
+# thirds in base 2, with a different multiplier
+base 2
+= 0 -> 1
+digits % 3 -> split 3
+any -> * 3").unwrap();
+        // Six binary digits split into thirds, four get multiplied by 3
+        assert_eq!(rules.blink(0b101101), Ok(vec![0b10, 0b11, 0b01]));
+        assert_eq!(rules.blink(0b1000), Ok(vec![0b11000]));
+        assert_eq!(rules.blink(0b100), Ok(vec![0b1, 0b0, 0b0]));
+        assert_eq!(rules.digits_count(0b100), 3);
+
+        let histogram = value_histogram(&rules, &[0], 3).unwrap();
+        // 0 -> 1 -> 3 (0b11) -> 9 (0b1001)
+        assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(9, 1)]);
+        assert_eq!(count_memoised(&rules, &[0], 3, None), Ok(1));
+        assert_eq!(value_histogram(&rules, &[0], 20).unwrap().values().sum::<u128>(), count_memoised(&rules, &[0], 20, None).unwrap());
+
+        let unmatched = RuleSet::parse("= 1 -> + 1").unwrap();
+        assert_eq!(unmatched.blink(1), Ok(vec![2]));
+        assert_eq!(unmatched.blink(5), Ok(vec![5]));
+    }
+
+    #[test]
+    fn test_rule_parse_errors() {
+        assert!(RuleSet::parse("any => 1").is_err());
+        assert!(RuleSet::parse("odd -> 1").is_err());
+        assert!(RuleSet::parse("any -> split 0").is_err());
+        assert!(RuleSet::parse("base 1").is_err());
+        assert!(RuleSet::parse("any -> * x").is_err());
+    }
+
+    #[test]
+    fn test_splits_have_to_be_even() {
+        // 1234 can't be cut into thirds, and a single digit not into halves
+        assert!(RuleSet::parse("any -> split 3").is_err());
+        assert!(RuleSet::parse("digits % 2 -> split 3").is_err());
+        assert!(RuleSet::parse("= 1234 -> split 3").is_err());
+        assert!(RuleSet::parse("= 5 -> split 2").is_err());
+        // Checked in the base given further down
+        assert!(RuleSet::parse("= 8 -> split 2\nbase 2").is_ok());
+
+        let rules = RuleSet::parse("= 123 -> split 3\ndigits % 4 -> split 2").unwrap();
+        assert_eq!(rules.blink(123), Ok(vec![1, 2, 3]));
+        assert_eq!(rules.blink(1234), Ok(vec![12, 34]));
+        assert_eq!(rules.blink(12), Ok(vec![12]));
+    }
+
+    #[test]
+    fn test_memoised_count_modulo() {
+        let rules = RuleSet::puzzle();
+        let modulus = 1_000_007;
+        let exact = count_memoised(&rules, &[125, 17], 75, None).unwrap();
+        assert_eq!(count_memoised(&rules, &[125, 17], 75, Some(modulus)), Ok(exact % modulus as u128));
+        assert_eq!(count_memoised(&rules, &[125, 17], 0, Some(1)), Ok(0));
+    }
+
+    #[test]
+    fn test_unbounded_growth() {
+        // 1 doubles every blink and outgrows usize on the 64th
+        let rules = RuleSet::parse("any -> * 2").unwrap();
+        assert!(TransitionMatrix::new(&[1], &rules).is_none());
+        assert_eq!(count_memoised(&rules, &[1], 63, None), Ok(1));
+        assert!(count_memoised(&rules, &[1], 64, None).is_err());
+        assert!(count_memoised(&rules, &[1], 64, Some(7)).is_err());
+        assert_eq!(value_histogram(&rules, &[1], 63).unwrap().into_iter().collect::<Vec<_>>(), vec![(1 << 63, 1)]);
+        assert!(value_histogram(&rules, &[1], 64).is_err());
+        assert!(RuleSet::parse("any -> + 1").unwrap().blink(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_histogram_matches_example() {
+        // 2097446912 14168 4048 2 0 2 4 40 48 2024 40 48 80 96 2 8 6 7 6 0 3 2 after 6 blinks
+        let histogram = value_histogram(&RuleSet::puzzle(), &[125, 17], 6).unwrap();
+        assert_eq!(histogram[&2], 4);
+        assert_eq!(histogram[&0], 2);
+        assert_eq!(histogram.values().sum::<u128>(), 22);
+    }
 }
@@ -1,4 +1,5 @@
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
 use glam::IVec2;
 use itertools::Itertools;
 use crate::day::utils;
@@ -8,6 +9,17 @@ pub fn run() {
     let input_string = utils::read_input(12);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+
+    // `cargo run -- 12 svg=garden.svg` draws the regions and their fences to have a look at.
+    // `cargo run -- 12 3,4,A 5,1,X` replants cells one after another and prints the prices after each.
+    let args = std::env::args().skip(2).collect_vec();
+    if let Some(file) = args.first().and_then(|arg| arg.strip_prefix("svg=")) {
+        let matrix = utils::input_into_matrix(&input_string);
+        let svg = render_svg(&matrix, &analyse_regions(&matrix));
+        std::fs::write(file, svg).expect("Failed to write SVG file");
+    } else if !args.is_empty() {
+        print_replanting(&input_string, &args);
+    }
 }
 
 static NEIGHBOUR_DELTAS: [IVec2; 4] = [
@@ -17,216 +29,518 @@ static NEIGHBOUR_DELTAS: [IVec2; 4] = [
     IVec2::new(-1, 0),
 ];
 
-fn run_part_one(input_string: &str) -> usize {
-    let mut matrix = Matrix::new();
-
-    input_string.lines()
-        .for_each(|line| matrix.push(line.chars().into_iter().collect()));
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Region {
+    plant: char,
+    cells: Vec<IVec2>,
+    area: usize,
+    perimeter: usize,
+    sides: usize,
+    holes: usize,
+    // Top left and bottom right cell
+    bounding_box: (IVec2, IVec2),
+    // Corner points of the fences, cell (x, y) spanning (x, y) to (x + 1, y + 1). The outer boundary comes first
+    // and runs clockwise, holes after it run anticlockwise.
+    outlines: Vec<Vec<IVec2>>,
+}
 
+fn analyse_regions(matrix: &Matrix) -> Vec<Region> {
     let mut visited = vec![vec![false; matrix[0].len()]; matrix.len()];
-
     let mut regions = Vec::new();
 
     for (y, row) in matrix.iter().enumerate() {
-        for (x, _) in row.iter().enumerate() {
-
-            if !visited[y][x] {
-                let mut section_area = 0;
-                let mut section_perimeter = 0;
-
-                let mut queue = VecDeque::new();
-                queue.push_back(IVec2 { x: x as i32, y: y as i32 });
-
-                while let Some(position) = queue.pop_front() {
-                    if visited[position.y as usize][position.x as usize] {
-                        continue;
-                    }
-                    visited[position.y as usize][position.x as usize] = true;
-                    section_area += 1;
-
-                    for delta in NEIGHBOUR_DELTAS {
-                        let neighbour = position + delta;
-                        if neighbour.x < 0 || neighbour.y < 0 || neighbour.y >= matrix.len() as i32 || neighbour.x >= matrix[0].len() as i32 {
-                            section_perimeter += 1;
-                            continue;
-                        }
+        for (x, &plant) in row.iter().enumerate() {
+            if visited[y][x] {
+                continue;
+            }
 
-                        if matrix[neighbour.y as usize][neighbour.x as usize] == matrix[position.y as usize][position.x as usize] {
-                            if !visited[neighbour.y as usize][neighbour.x as usize] {
-                                queue.push_back(neighbour);
-                            }
-                        } else {
-                            section_perimeter += 1;
-                        }
+            let mut cells = Vec::new();
+            let mut queue = VecDeque::new();
+            queue.push_back(IVec2 { x: x as i32, y: y as i32 });
+            visited[y][x] = true;
+            while let Some(position) = queue.pop_front() {
+                cells.push(position);
+                for delta in NEIGHBOUR_DELTAS {
+                    let neighbour = position + delta;
+                    if plant_at(matrix, neighbour) == Some(plant) && !visited[neighbour.y as usize][neighbour.x as usize] {
+                        visited[neighbour.y as usize][neighbour.x as usize] = true;
+                        queue.push_back(neighbour);
                     }
                 }
-
-                regions.push((section_area, section_perimeter));
             }
+
+            regions.push(describe_region(matrix, plant, cells));
         }
     }
 
-    regions.iter().map(|(area, perimeter)| {
-        area * perimeter
-    }).sum()
+    regions
 }
 
-fn run_part_two(input_string: &str) -> usize {
-    let mut matrix = Matrix::new();
+fn plant_at(matrix: &Matrix, position: IVec2) -> Option<char> {
+    if position.x < 0 || position.y < 0 || position.y >= matrix.len() as i32 || position.x >= matrix[0].len() as i32 {
+        return None;
+    }
+    Some(matrix[position.y as usize][position.x as usize])
+}
 
-    input_string.lines()
-        .for_each(|line| matrix.push(line.chars().into_iter().collect()));
+fn describe_region(matrix: &Matrix, plant: char, cells: Vec<IVec2>) -> Region {
+    let in_region = |position: IVec2| plant_at(matrix, position) == Some(plant);
 
-    let mut visited = vec![vec![false; matrix[0].len()]; matrix.len()];
+    let perimeter = cells.iter().map(|&cell| fence_count(cell, in_region)).sum();
+    let sides = cells.iter().map(|&cell| corner_count(cell, in_region)).sum();
 
-    let mut regions = Vec::new();
-    // Store regions as pair of area and list of fence positions instead
-    //   -> Vec<(usize, Vec<(IVec2,IVec2)>)>
+    let min = cells.iter().copied().reduce(IVec2::min).unwrap();
+    let max = cells.iter().copied().reduce(IVec2::max).unwrap();
+    let outlines = trace_outlines(&cells, in_region);
+    let holes = outlines.len() - 1;
 
-    for (y, row) in matrix.iter().enumerate() {
-        for (x, _) in row.iter().enumerate() {
+    Region { plant, area: cells.len(), cells, perimeter, sides, holes, bounding_box: (min, max), outlines }
+}
 
-            if !visited[y][x] {
-                let mut section_area = 0;
-                // todo: does it need to be a set instead?
-                let mut section_perimeter:Vec<(IVec2, IVec2)> = Vec::new();
+fn fence_count(cell: IVec2, in_region: impl Fn(IVec2) -> bool) -> usize {
+    NEIGHBOUR_DELTAS.iter().filter(|&&delta| !in_region(cell + delta)).count()
+}
 
-                let mut queue = VecDeque::new();
-                queue.push_back(IVec2 { x: x as i32, y: y as i32 });
+// A region has as many sides as corners. Looking at each corner of a cell, it's an outside corner if both
+// neighbours towards it are missing, and an inside one if both are there but the diagonal isn't.
+fn corner_count(cell: IVec2, in_region: impl Fn(IVec2) -> bool) -> usize {
+    CORNER_DELTAS.iter()
+        .filter(|&&(horizontal, vertical)| {
+            let has_horizontal = in_region(cell + horizontal);
+            let has_vertical = in_region(cell + vertical);
+            (!has_horizontal && !has_vertical)
+                || (has_horizontal && has_vertical && !in_region(cell + horizontal + vertical))
+        })
+        .count()
+}
 
-                while let Some(position) = queue.pop_front() {
-                    if visited[position.y as usize][position.x as usize] {
-                        continue;
-                    }
-                    visited[position.y as usize][position.x as usize] = true;
-                    section_area += 1;
-
-                    for delta in NEIGHBOUR_DELTAS {
-                        let neighbour = position + delta;
-                        if neighbour.x < 0 || neighbour.y < 0 || neighbour.y >= matrix.len() as i32 || neighbour.x >= matrix[0].len() as i32 {
-                            section_perimeter.push((position, delta));
-                            continue;
-                        }
+static CORNER_DELTAS: [(IVec2, IVec2); 4] = [
+    (IVec2::new(-1, 0), IVec2::new(0, -1)),
+    (IVec2::new(1, 0), IVec2::new(0, -1)),
+    (IVec2::new(1, 0), IVec2::new(0, 1)),
+    (IVec2::new(-1, 0), IVec2::new(0, 1)),
+];
 
-                        if matrix[neighbour.y as usize][neighbour.x as usize] == matrix[position.y as usize][position.x as usize] {
-                            if !visited[neighbour.y as usize][neighbour.x as usize] {
-                                queue.push_back(neighbour);
-                            }
-                        } else {
-                            section_perimeter.push((position, delta));
-                        }
-                    }
+// Fence pieces as edges between corner points, directed so that the region is on the right (clockwise on
+// screen, with y pointing down)
+fn fence_edges(cell: IVec2, in_region: impl Fn(IVec2) -> bool) -> Vec<(IVec2, IVec2)> {
+    let top_left = cell;
+    let top_right = cell + IVec2::new(1, 0);
+    let bottom_right = cell + IVec2::new(1, 1);
+    let bottom_left = cell + IVec2::new(0, 1);
+    [
+        (IVec2::new(0, -1), top_left, top_right),
+        (IVec2::new(1, 0), top_right, bottom_right),
+        (IVec2::new(0, 1), bottom_right, bottom_left),
+        (IVec2::new(-1, 0), bottom_left, top_left),
+    ]
+        .into_iter()
+        .filter(|&(delta, _, _)| !in_region(cell + delta))
+        .map(|(_, from, to)| (from, to))
+        .collect()
+}
+
+// Chains the fence pieces into closed polygons, keeping only the points where the fence turns. Where two cells
+// of the region only touch diagonally the fence turns away from the region, so that two holes touching at a
+// corner stay separate holes.
+fn trace_outlines(cells: &[IVec2], in_region: impl Fn(IVec2) -> bool) -> Vec<Vec<IVec2>> {
+    let mut outgoing: HashMap<IVec2, Vec<IVec2>> = HashMap::new();
+    for &cell in cells {
+        for (from, to) in fence_edges(cell, &in_region) {
+            outgoing.entry(from).or_default().push(to);
+        }
+    }
+
+    let mut outlines = Vec::new();
+    // The first point of every loop is its top left corner, so it's always a turn
+    while let Some(&start) = outgoing.keys().min_by_key(|point| (point.y, point.x)) {
+        let mut outline = vec![start];
+        let mut point = start;
+        let mut direction: Option<IVec2> = None;
+        loop {
+            let targets = outgoing.get_mut(&point).unwrap();
+            let index = match direction {
+                // Turning left, away from the region
+                Some(direction) if targets.len() > 1 => targets.iter()
+                    .position(|&target| target - point == IVec2::new(direction.y, -direction.x))
+                    .unwrap(),
+                _ => 0,
+            };
+            let next = targets.swap_remove(index);
+            if targets.is_empty() {
+                outgoing.remove(&point);
+            }
+
+            let next_direction = next - point;
+            if direction.is_some_and(|direction| direction != next_direction) {
+                outline.push(point);
+            }
+            direction = Some(next_direction);
+            point = next;
+            if point == start {
+                break;
+            }
+        }
+        outlines.push(outline);
+    }
+
+    // Outer boundary first, holes have a negative area going the other way round
+    outlines.sort_by_key(|outline| Reverse(signed_area(outline)));
+    outlines
+}
+
+// Twice the area enclosed by the polygon, positive when running clockwise on screen
+fn signed_area(outline: &[IVec2]) -> i32 {
+    outline.iter().circular_tuple_windows()
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum()
+}
+
+fn total_price(regions: &[Region], price: impl Fn(&Region) -> usize) -> usize {
+    regions.iter().map(price).sum()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RegionStats {
+    area: isize,
+    perimeter: isize,
+    sides: isize,
+}
+
+impl RegionStats {
+    fn add(self, other: RegionStats) -> RegionStats {
+        RegionStats { area: self.area + other.area, perimeter: self.perimeter + other.perimeter, sides: self.sides + other.sides }
+    }
+
+    fn negate(self) -> RegionStats {
+        RegionStats { area: -self.area, perimeter: -self.perimeter, sides: -self.sides }
+    }
+}
+
+// Keeps the regions of a garden up to date while cells get replanted. Cells point at union-find nodes and every
+// root holds the stats of its region. Perimeter and sides add up per cell and only depend on the cells around
+// it, so a replanted cell only changes the numbers of its 3x3 neighbourhood. Joining regions is a union, a
+// region falling apart is found with a search over it and the split off parts get new nodes.
+struct RegionStore {
+    matrix: Matrix,
+    cell_nodes: Vec<Vec<usize>>,
+    parents: Vec<usize>,
+    stats: Vec<RegionStats>,
+    region_count: usize,
+    price_by_perimeter: isize,
+    price_by_sides: isize,
+}
+
+impl RegionStore {
+    fn new(matrix: Matrix) -> RegionStore {
+        // Cells start out with one node each, numbered row by row
+        let width = matrix[0].len();
+        let cell_count = width * matrix.len();
+        let mut store = RegionStore {
+            cell_nodes: (0..matrix.len()).map(|y| (0..width).map(|x| y * width + x).collect()).collect(),
+            parents: (0..cell_count).collect(),
+            stats: vec![RegionStats::default(); cell_count],
+            region_count: 0,
+            price_by_perimeter: 0,
+            price_by_sides: 0,
+            matrix,
+        };
+
+        let cells = store.cells().collect_vec();
+        for &cell in &cells {
+            for delta in [IVec2::new(1, 0), IVec2::new(0, 1)] {
+                if store.plant(cell + delta) == store.plant(cell) {
+                    store.union(store.node(cell), store.node(cell + delta));
                 }
-                regions.push((section_area, section_perimeter));
             }
         }
+        for &cell in &cells {
+            let root = store.find(store.node(cell));
+            store.adjust(root, RegionStats { area: 1, ..store.cell_stats(cell) });
+        }
+        store
+    }
+
+    fn cells(&self) -> impl Iterator<Item = IVec2> + use<> {
+        let width = self.matrix[0].len() as i32;
+        (0..self.matrix.len() as i32).flat_map(move |y| (0..width).map(move |x| IVec2::new(x, y)))
     }
 
-    let mut sum = 0;
+    fn plant(&self, position: IVec2) -> Option<char> {
+        plant_at(&self.matrix, position)
+    }
 
-    for (area, perimeter_sections) in regions {
-        let mut fences_count = 0;
-        let mut visited_sections = vec![false; perimeter_sections.len()];
+    fn node(&self, cell: IVec2) -> usize {
+        self.cell_nodes[cell.y as usize][cell.x as usize]
+    }
 
-        // silly way of grouping perimeter sections by adjacency and direction (delta) to get
-        // fence counts, pm me better way to aggregate
-        for i in 0..perimeter_sections.len() {
-            if visited_sections[i] {
-                continue;
+    fn new_node(&mut self) -> usize {
+        self.parents.push(self.parents.len());
+        self.stats.push(RegionStats::default());
+        self.parents.len() - 1
+    }
+
+    fn find(&mut self, node: usize) -> usize {
+        let mut root = node;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        let mut node = node;
+        while self.parents[node] != root {
+            let next = self.parents[node];
+            self.parents[node] = root;
+            node = next;
+        }
+        root
+    }
+
+    fn union(&mut self, first: usize, second: usize) {
+        let (first, second) = (self.find(first), self.find(second));
+        if first == second {
+            return;
+        }
+        // Hang the smaller region below the bigger one, moving its stats over
+        let (root, child) = if self.stats[first].area >= self.stats[second].area { (first, second) } else { (second, first) };
+        let moved = self.stats[child];
+        self.adjust(child, moved.negate());
+        self.adjust(root, moved);
+        self.parents[child] = root;
+    }
+
+    // Changes the stats of a region, keeping the totals in line
+    fn adjust(&mut self, root: usize, delta: RegionStats) {
+        let before = self.stats[root];
+        let after = before.add(delta);
+        self.price_by_perimeter += after.area * after.perimeter - before.area * before.perimeter;
+        self.price_by_sides += after.area * after.sides - before.area * before.sides;
+        if before.area == 0 && after.area > 0 {
+            self.region_count += 1;
+        } else if before.area > 0 && after.area == 0 {
+            self.region_count -= 1;
+        }
+        self.stats[root] = after;
+    }
+
+    // What a cell adds to the perimeter and sides of its region
+    fn cell_stats(&self, cell: IVec2) -> RegionStats {
+        let plant = self.plant(cell);
+        let in_region = |position: IVec2| self.plant(position) == plant;
+        RegionStats { area: 0, perimeter: fence_count(cell, in_region) as isize, sides: corner_count(cell, in_region) as isize }
+    }
+
+    fn region_stats(&mut self, x: usize, y: usize) -> RegionStats {
+        let root = self.find(self.cell_nodes[y][x]);
+        self.stats[root]
+    }
+
+    fn replant(&mut self, x: usize, y: usize, plant: char) {
+        let cell = IVec2::new(x as i32, y as i32);
+        let old_plant = self.matrix[y][x];
+        if old_plant == plant {
+            return;
+        }
+
+        let neighbourhood = (-1..=1).cartesian_product(-1..=1)
+            .map(|(dx, dy)| cell + IVec2::new(dx, dy))
+            .filter(|&position| self.plant(position).is_some())
+            .collect_vec();
+        for &position in &neighbourhood {
+            let root = self.find(self.node(position));
+            self.adjust(root, self.cell_stats(position).negate());
+        }
+
+        // The old node stays behind in the old region's tree, the cell starts over in a region of its own
+        let old_root = self.find(self.node(cell));
+        self.adjust(old_root, RegionStats { area: -1, ..RegionStats::default() });
+        self.matrix[y][x] = plant;
+        let node = self.new_node();
+        self.cell_nodes[y][x] = node;
+        self.adjust(node, RegionStats { area: 1, ..RegionStats::default() });
+        for delta in NEIGHBOUR_DELTAS {
+            if self.plant(cell + delta) == Some(plant) {
+                self.union(node, self.node(cell + delta));
             }
-            let current = perimeter_sections[i];
-            let is_horizontal_fence = current.1.x == 0;
-            fences_count += 1;
-
-            // find all matching positions horizontally with same delta
-            let adjacent_perimeters = perimeter_sections.iter().enumerate()
-                .filter(|(_, elem)| elem.1 == current.1)
-                .filter(|(_, elem)| {
-                    if is_horizontal_fence {
-                        elem.0.y == current.0.y
-                    } else {
-                        elem.0.x == current.0.x
-                    }
-                })
-                .sorted_by(|first, second| {
-                    if is_horizontal_fence {
-                        // if delta x = 0, then horizontal fence and order by X
-                        Ord::cmp(&first.1.0.x, &second.1.0.x)
-                    } else {
-                        // if delta y = 0, then vertical fence and order by Y
-                        Ord::cmp(&first.1.0.y, &second.1.0.y)
-                    }
-                }).collect_vec();
+        }
 
-            // if there is a bug up until here then idno wat do
+        for &position in &neighbourhood {
+            let root = self.find(self.node(position));
+            self.adjust(root, self.cell_stats(position));
+        }
 
-            // find only adjacent in the list
-            let current_index = adjacent_perimeters.iter()
-                .position(|&x| *x.1 == current).unwrap();
+        self.split_off(cell, old_plant);
+    }
 
-            let mut current_x = current.0.x;
-            let mut current_y = current.0.y;
+    // After a cell was taken out of its region, the neighbours that were in it may no longer be connected. They
+    // are searched from all at once, one cell each in turn, merging searches that run into each other. Once at most
+    // one search is still going, the others have gone through the whole of their parts, which move to new nodes.
+    // That way the time taken depends on the size of the parts split off, not on the size of the region.
+    fn split_off(&mut self, cell: IVec2, old_plant: char) {
+        let old_neighbours = NEIGHBOUR_DELTAS.iter()
+            .map(|&delta| cell + delta)
+            .filter(|&position| self.plant(position) == Some(old_plant))
+            .collect_vec();
+        if old_neighbours.len() < 2 {
+            return;
+        }
 
-            for j in current_index+1..adjacent_perimeters.len() {
-                if is_horizontal_fence {
-                    // x diff is 1
-                    if adjacent_perimeters[j].1.0.x - current_x == 1 {
-                        visited_sections[adjacent_perimeters[j].0] = true;
-                        current_x += 1;
-                    } else {
-                        break;
-                    }
-                } else {
-                    // y diff is 1
-                    if adjacent_perimeters[j].1.0.y - current_y == 1 {
-                        visited_sections[adjacent_perimeters[j].0] = true;
-                        current_y += 1;
-                    } else {
-                        break;
-                    }
-                }
+        let mut searches = old_neighbours.iter()
+            .map(|&start| PartSearch { merged_into: None, queue: VecDeque::from([start]), cells: vec![start] })
+            .collect_vec();
+        let mut owners: HashMap<IVec2, usize> = old_neighbours.iter().enumerate().map(|(index, &start)| (start, index)).collect();
+        let find_search = |searches: &[PartSearch], mut index: usize| {
+            while let Some(parent) = searches[index].merged_into {
+                index = parent;
+            }
+            index
+        };
+
+        loop {
+            let running = (0..searches.len())
+                .filter(|&index| searches[index].merged_into.is_none() && !searches[index].queue.is_empty())
+                .collect_vec();
+            let remaining = searches.iter().filter(|search| search.merged_into.is_none()).count();
+            if remaining == 1 {
+                return;
+            }
+            if running.len() <= 1 {
+                break;
             }
 
-            current_x = current.0.x;
-            current_y = current.0.y;
-
-            for j in (0..current_index).rev() {
-                if is_horizontal_fence {
-                    // x diff is 1
-                    if adjacent_perimeters[j].1.0.x - current_x == -1 {
-                        visited_sections[adjacent_perimeters[j].0] = true;
-                        current_x -= 1;
-                    } else {
-                        break;
+            for index in running {
+                if searches[index].merged_into.is_some() {
+                    continue;
+                }
+                let Some(position) = searches[index].queue.pop_front() else { continue };
+                for delta in NEIGHBOUR_DELTAS {
+                    let neighbour = position + delta;
+                    if self.plant(neighbour) != Some(old_plant) {
+                        continue;
                     }
-                } else {
-                    // y diff is 1
-                    if adjacent_perimeters[j].1.0.y - current_y == -1 {
-                        visited_sections[adjacent_perimeters[j].0] = true;
-                        current_y -= 1;
-                    } else {
-                        break;
+                    match owners.get(&neighbour) {
+                        Some(&owner) => {
+                            let (own, other) = (find_search(&searches, index), find_search(&searches, owner));
+                            if own != other {
+                                // Keep the bigger one going, so that the cells are moved as few times as possible
+                                let (kept, merged) = if searches[own].cells.len() >= searches[other].cells.len() { (own, other) } else { (other, own) };
+                                let PartSearch { queue, cells, .. } = std::mem::take(&mut searches[merged]);
+                                searches[merged].merged_into = Some(kept);
+                                searches[kept].queue.extend(queue);
+                                searches[kept].cells.extend(cells);
+                            }
+                        }
+                        None => {
+                            let own = find_search(&searches, index);
+                            owners.insert(neighbour, own);
+                            searches[own].queue.push_back(neighbour);
+                            searches[own].cells.push(neighbour);
+                        }
                     }
                 }
             }
+        }
 
-
-            visited_sections[i] = true;
+        // All parts but possibly the one still being searched are complete. The biggest one stays in the old node.
+        let mut parts = searches.into_iter().filter(|search| search.merged_into.is_none()).collect_vec();
+        parts.sort_by_key(|search| (search.queue.is_empty(), std::cmp::Reverse(search.cells.len())));
+        for part in parts.iter().skip(1) {
+            let old_root = self.find(self.node(part.cells[0]));
+            let part_stats = part.cells.iter()
+                .fold(RegionStats::default(), |total, &position| total.add(RegionStats { area: 1, ..self.cell_stats(position) }));
+            self.adjust(old_root, part_stats.negate());
+
+            let root = self.new_node();
+            for &position in &part.cells {
+                self.cell_nodes[position.y as usize][position.x as usize] = root;
+            }
+            self.adjust(root, part_stats);
         }
+    }
+}
 
-        // println!("");
+// One of the searches of `RegionStore::split_off`
+#[derive(Default)]
+struct PartSearch {
+    merged_into: Option<usize>,
+    queue: VecDeque<IVec2>,
+    cells: Vec<IVec2>,
+}
 
-        // sum += area * fences_count;
-        // println!("area: {}, fences: {}", area, fences_count);
-        sum += area * fences_count;
+fn print_replanting(input_string: &str, edits: &[String]) {
+    let mut store = RegionStore::new(utils::input_into_matrix(input_string));
+    for edit in edits {
+        let (x, y, plant) = edit.split(',').collect_tuple().expect("Replanting has to be given as x,y,plant");
+        let (x, y) = (x.parse().unwrap(), y.parse().unwrap());
+        store.replant(x, y, plant.chars().next().unwrap());
+        let stats = store.region_stats(x, y);
+        println!("{},{} -> {}: region of area {}, perimeter {}, {} sides; {} regions, prices {} and {}",
+                 x, y, plant, stats.area, stats.perimeter, stats.sides,
+                 store.region_count, store.price_by_perimeter, store.price_by_sides);
     }
+}
+
+const SVG_CELL_SIZE: i32 = 20;
+
+// Plants can be any character, including ones with a meaning in SVG
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Draws every region filled with its own colour and fenced along its outlines, labelled with its side count in
+// its top left cell. Hovering a region shows its measurements.
+fn render_svg(matrix: &Matrix, regions: &[Region]) -> String {
+    let width = matrix[0].len() as i32 * SVG_CELL_SIZE;
+    let height = matrix.len() as i32 * SVG_CELL_SIZE;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+                          width, height, width, height);
+    svg += &format!("<desc>{} regions, price by perimeter {}, price by sides {}</desc>\n",
+                    regions.len(),
+                    total_price(regions, |region| region.area * region.perimeter),
+                    total_price(regions, |region| region.area * region.sides));
+
+    for (index, region) in regions.iter().enumerate() {
+        // Golden angle steps keep neighbouring regions apart in colour
+        let hue = (index * 137) % 360;
+        let path = region.outlines.iter()
+            .map(|outline| {
+                let points = outline.iter()
+                    .map(|point| format!("{},{}", point.x * SVG_CELL_SIZE, point.y * SVG_CELL_SIZE))
+                    .join(" L ");
+                format!("M {} Z", points)
+            })
+            .join(" ");
+        let label_cell = region.cells.iter().min_by_key(|cell| (cell.y, cell.x)).unwrap();
+
+        svg += "<g>\n";
+        svg += &format!("<title>{}: area {}, perimeter {}, {} sides, {} holes</title>\n",
+                        escape_xml(&region.plant.to_string()), region.area, region.perimeter, region.sides, region.holes);
+        svg += &format!("<path d=\"{}\" fill=\"hsl({}, 60%, 75%)\" fill-rule=\"evenodd\" stroke=\"black\" stroke-width=\"2\"/>\n",
+                        path, hue);
+        svg += &format!("<text x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>\n",
+                        label_cell.x * SVG_CELL_SIZE + SVG_CELL_SIZE / 2,
+                        label_cell.y * SVG_CELL_SIZE + SVG_CELL_SIZE / 2,
+                        SVG_CELL_SIZE / 2,
+                        region.sides);
+        svg += "</g>\n";
+    }
+
+    svg += "</svg>\n";
+    svg
+}
 
-    sum
+fn run_part_one(input_string: &str) -> usize {
+    let regions = analyse_regions(&utils::input_into_matrix(input_string));
+    total_price(&regions, |region| region.area * region.perimeter)
+}
+
+fn run_part_two(input_string: &str) -> usize {
+    let regions = analyse_regions(&utils::input_into_matrix(input_string));
+    total_price(&regions, |region| region.area * region.sides)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day12::{run_part_one, run_part_two};
+    use crate::day::day12::{analyse_regions, render_svg, total_price, RegionStats, RegionStore, run_part_one, run_part_two, signed_area};
+    use glam::IVec2;
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -304,4 +618,145 @@ AAAAAA")
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(12)), 891106);
     }
+
+    #[test]
+    fn test_region_analysis() {
+        let regions = analyse_regions(&utils::input_into_matrix(&example_input_2()));
+        assert_eq!(regions.len(), 5);
+        let outer = &regions[0];
+        assert_eq!((outer.plant, outer.area, outer.perimeter, outer.sides, outer.holes), ('O', 21, 36, 20, 4));
+        assert_eq!(outer.bounding_box, (IVec2::new(0, 0), IVec2::new(4, 4)));
+        assert_eq!(outer.outlines[0], vec![IVec2::new(0, 0), IVec2::new(5, 0), IVec2::new(5, 5), IVec2::new(0, 5)]);
+        assert_eq!(outer.outlines[1], vec![IVec2::new(1, 1), IVec2::new(1, 2), IVec2::new(2, 2), IVec2::new(2, 1)]);
+        assert!(regions[1..].iter().all(|region| region.plant == 'X' && region.sides == 4 && region.holes == 0));
+    }
+
+    #[test]
+    fn test_outlines_match_region() {
+        for input in [example_input(), example_input_2(), example_input_3(), example_input_4(), example_input_5(), utils::read_input(12)] {
+            for region in analyse_regions(&utils::input_into_matrix(&input)) {
+                assert_eq!(region.outlines.iter().map(|outline| outline.len()).sum::<usize>(), region.sides);
+                assert_eq!(region.outlines.iter().map(|outline| signed_area(outline)).sum::<i32>(), 2 * region.area as i32);
+                assert!(signed_area(&region.outlines[0]) > 0);
+                assert!(region.outlines[1..].iter().all(|hole| signed_area(hole) < 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_diagonal_touching_cells() {
+        // The B regions of the last example touch diagonally in the middle, so the A region has two holes with a
+        // shared corner
+        let regions = analyse_regions(&utils::input_into_matrix(&example_input_5()));
+        let region_a = regions.iter().find(|region| region.plant == 'A').unwrap();
+        assert_eq!((region_a.area, region_a.sides, region_a.holes), (28, 12, 2));
+        assert_eq!(region_a.outlines[1], vec![IVec2::new(3, 1), IVec2::new(3, 3), IVec2::new(5, 3), IVec2::new(5, 1)]);
+        assert_eq!(region_a.outlines[2], vec![IVec2::new(1, 3), IVec2::new(1, 5), IVec2::new(3, 5), IVec2::new(3, 3)]);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let matrix = utils::input_into_matrix(&example_input_2());
+        let svg = render_svg(&matrix, &analyse_regions(&matrix));
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"100\" height=\"100\""));
+        assert!(svg.contains("<desc>5 regions, price by perimeter 772, price by sides 436</desc>"));
+        assert_eq!(svg.matches("<path ").count(), 5);
+        // The O region with its four holes, then the first X labelled with its 4 sides
+        assert!(svg.contains("d=\"M 0,0 L 100,0 L 100,100 L 0,100 Z M 20,20 L 20,40 L 40,40 L 40,20 Z M 60,20"));
+        assert!(svg.contains("<title>O: area 21, perimeter 36, 20 sides, 4 holes</title>"));
+        assert!(svg.contains("<text x=\"30\" y=\"30\" font-size=\"10\" text-anchor=\"middle\" dominant-baseline=\"central\">4</text>"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+
+        let matrix = utils::input_into_matrix("&<\n\"\"");
+        let svg = render_svg(&matrix, &analyse_regions(&matrix));
+        assert!(svg.contains("<title>&amp;: area 1,"));
+        assert!(svg.contains("<title>&lt;: area 1,"));
+        assert!(svg.contains("<title>&quot;: area 2,"));
+    }
+
+    fn assert_store_matches_analysis(store: &RegionStore) {
+        let regions = analyse_regions(&store.matrix);
+        assert_eq!(store.region_count, regions.len());
+        assert_eq!(store.price_by_perimeter as usize, total_price(&regions, |region| region.area * region.perimeter));
+        assert_eq!(store.price_by_sides as usize, total_price(&regions, |region| region.area * region.sides));
+    }
+
+    #[test]
+    fn test_region_store_prices() {
+        for input in [example_input(), example_input_2(), example_input_3(), example_input_4(), example_input_5()] {
+            assert_store_matches_analysis(&RegionStore::new(utils::input_into_matrix(&input)));
+        }
+        let store = RegionStore::new(utils::input_into_matrix(&utils::read_input(12)));
+        assert_eq!((store.price_by_perimeter, store.price_by_sides), (1434856, 891106));
+    }
+
+    #[test]
+    fn test_region_store_merge_and_split() {
+        let mut store = RegionStore::new(utils::input_into_matrix(&example_input_2()));
+        // Filling in a hole merges it into the O region
+        store.replant(1, 1, 'O');
+        assert_eq!(store.region_count, 4);
+        assert_eq!(store.region_stats(1, 1).area, 22);
+        assert_store_matches_analysis(&store);
+
+        // A row of X across the middle cuts the O region in two
+        for x in 0..5 {
+            store.replant(x, 2, 'X');
+        }
+        assert_eq!(store.region_stats(0, 0).area, 9);
+        assert_eq!(store.region_stats(0, 4).area, 8);
+        assert_eq!(store.region_stats(0, 2), store.region_stats(3, 1));
+        assert_eq!(store.region_stats(0, 2).area, 8);
+        assert_store_matches_analysis(&store);
+    }
+
+    #[test]
+    fn test_region_store_random_edits() {
+        let mut store = RegionStore::new(utils::input_into_matrix(&example_input_3()));
+        let mut state: u64 = 12;
+        for _ in 0..300 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let x = (state >> 33) as usize % 10;
+            let y = (state >> 40) as usize % 10;
+            let plant = ['R', 'I', 'C', 'F'][(state >> 50) as usize % 4];
+            store.replant(x, y, plant);
+            assert_store_matches_analysis(&store);
+        }
+    }
+
+    #[test]
+    fn test_region_store_splits_off_small_parts() {
+        let garden = vec!["A".repeat(300); 300].join("\n");
+        let mut store = RegionStore::new(utils::input_into_matrix(&garden));
+        let far_node = store.node(IVec2::new(299, 299));
+
+        // Cutting off the corner only moves the corner cell to a new node
+        store.replant(1, 0, 'B');
+        store.replant(0, 1, 'B');
+        assert_eq!(store.region_count, 4);
+        assert_eq!(store.region_stats(0, 0), RegionStats { area: 1, perimeter: 4, sides: 4 });
+        assert_eq!(store.region_stats(299, 299).area, 300 * 300 - 3);
+        assert_eq!(store.node(IVec2::new(299, 299)), far_node);
+        assert_eq!(store.price_by_perimeter, 4 + 4 + 4 + (300 * 300 - 3) * 4 * 300);
+
+        // Joined back up again
+        store.replant(1, 0, 'A');
+        assert_eq!(store.region_count, 2);
+        assert_eq!(store.region_stats(0, 0).area, 300 * 300 - 1);
+    }
+
+    #[test]
+    fn test_region_store_random_splits() {
+        // With only two plants, edits keep cutting regions apart and joining them
+        let garden = vec!["AB".repeat(10); 20].join("\n");
+        let mut store = RegionStore::new(utils::input_into_matrix(&garden));
+        let mut state: u64 = 7;
+        for _ in 0..400 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let x = (state >> 33) as usize % 20;
+            let y = (state >> 40) as usize % 20;
+            store.replant(x, y, ['A', 'B'][(state >> 50) as usize % 2]);
+            assert_store_matches_analysis(&store);
+        }
+    }
 }
@@ -1,79 +1,357 @@
 use crate::day::utils;
-use glam::UVec2;
+use itertools::Itertools;
 use regex::Regex;
-use std::cmp::min;
+use std::fmt;
 
 pub fn run() {
     let input_string = utils::read_input(13);
     println!("{}", run_part_one(&input_string));
     println!("{}", run_part_two(&input_string));
+
+    // Other token prices can be tried with e.g. `cargo run -- 13 costs=1,1`, one cost per button. `limit=n` caps
+    // the total number of presses per machine, `explain` prints the presses or why there are none per machine.
+    let args = std::env::args().skip(2).collect_vec();
+    if !args.is_empty() {
+        print_solutions(&input_string, &args);
+    }
 }
 
-fn run_part_one(input_string: &str) -> usize {
-    let re: Regex = Regex::new(r"(?ms)X[+=](\d+), Y[+=](\d+).*X[+=](\d+), Y[+=](\d+).*X[+=](\d+), Y[+=](\d+)").unwrap();
-    let mut input = Vec::new();
+type Vector = (i128, i128);
 
-    let mut blocks = input_string.split("\n\n");
-    while let Some(block) = blocks.next() {
-        let caps = re.captures(block).unwrap();
-        let a = UVec2 {x: caps[1].parse().unwrap(), y: caps[2].parse().unwrap()};
-        let b = UVec2 {x: caps[3].parse().unwrap(), y: caps[4].parse().unwrap()};
-        let target = UVec2 {x: caps[5].parse().unwrap(), y: caps[6].parse().unwrap()};
-        input.push((a, b, target));
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Button {
+    name: String,
+    movement: Vector,
+    cost: i128,
+    limit: Option<i128>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClawMachine {
+    buttons: Vec<Button>,
+    prize: Vector,
+    // On the number of presses of all buttons together
+    total_limit: Option<i128>,
+}
 
-    let mut token_cost_total = 0;
-    for case in input.iter() {
-        let mut min_token_cost = usize::MAX;
+// Why a prize can't be won
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Infeasible {
+    // Not even fractional presses get there
+    OutsideCone,
+    // Presses reach the line or plane of the prize, but only ever hit positions around it
+    OutsideLattice,
+    // Whole numbers of presses land on the prize, but only when pressing some button a negative number of times
+    NoIntegerSolution,
+    // The prize can be won, just not within the limits
+    OverLimits,
+}
+
+impl fmt::Display for Infeasible {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Infeasible::OutsideCone => write!(f, "the buttons don't move towards the prize"),
+            Infeasible::OutsideLattice => write!(f, "no whole number of presses lands on the prize"),
+            Infeasible::NoIntegerSolution => write!(f, "landing on the prize takes pressing a button a negative number of times"),
+            Infeasible::OverLimits => write!(f, "the prize takes more presses than allowed"),
+        }
+    }
+}
 
-        for i in 0..100 { // press A
-            for j in 0..100 { // press B
-                let cost = i * 3 + j;
+// Machines list any number of buttons, optionally with their token cost and how often they may be pressed, like
+// `Button C: X+5, Y+7, cost 2, limit 10`. Without a cost, A costs 3 tokens and every other button 1. A
+// `Limit: n` line caps the presses of all buttons together.
+fn parse_claw_machines(input_string: &str, prize_offset: i128) -> Vec<ClawMachine> {
+    let button_re = Regex::new(r"^Button (\w+): X\+(\d+), Y\+(\d+)(?:, cost (\d+))?(?:, limit (\d+))?$").unwrap();
+    let prize_re = Regex::new(r"^Prize: X=(\d+), Y=(\d+)$").unwrap();
+    let limit_re = Regex::new(r"^Limit: (\d+)$").unwrap();
 
-                let reached_pos = (case.0 * i) + (case.1 * j);
-                if reached_pos == case.2 {
-                    min_token_cost = min(min_token_cost, cost as usize);
+    input_string.split("\n\n")
+        .map(|block| {
+            let mut machine = ClawMachine { buttons: vec![], prize: (0, 0), total_limit: None };
+            for line in block.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                if let Some(caps) = button_re.captures(line) {
+                    let name = caps[1].to_string();
+                    let default_cost = if name == "A" { 3 } else { 1 };
+                    machine.buttons.push(Button {
+                        movement: (caps[2].parse().unwrap(), caps[3].parse().unwrap()),
+                        cost: caps.get(4).map_or(default_cost, |cost| cost.as_str().parse().unwrap()),
+                        limit: caps.get(5).map(|limit| limit.as_str().parse().unwrap()),
+                        name,
+                    });
+                } else if let Some(caps) = prize_re.captures(line) {
+                    machine.prize = (caps[1].parse::<i128>().unwrap() + prize_offset, caps[2].parse::<i128>().unwrap() + prize_offset);
+                } else if let Some(caps) = limit_re.captures(line) {
+                    machine.total_limit = Some(caps[1].parse().unwrap());
+                } else {
+                    panic!("Unexpected line {:?}", line);
                 }
             }
+            machine
+        })
+        .collect()
+}
+
+fn token_cost(machine: &ClawMachine, presses: &[i128]) -> i128 {
+    machine.buttons.iter().zip(presses).map(|(button, presses)| button.cost * presses).sum()
+}
+
+fn run_part_one(input_string: &str) -> i128 {
+    parse_claw_machines(input_string, 0).iter_mut()
+        .flat_map(|machine| {
+            for button in &mut machine.buttons {
+                button.limit = Some(100);
+            }
+            solve_machine(machine).map(|presses| token_cost(machine, &presses))
+        })
+        .sum()
+}
+
+fn run_part_two(input_string: &str) -> i128 {
+    parse_claw_machines(input_string, 10000000000000).iter()
+        .flat_map(|machine| solve_machine(machine).map(|presses| token_cost(machine, &presses)))
+        .sum()
+}
+
+fn cross((x1, y1): Vector, (x2, y2): Vector) -> i128 {
+    x1 * y2 - y1 * x2
+}
+
+// Cheapest presses per button winning the prize, as a small integer linear programme. Buttons only move the
+// claw forwards, so every press count is bounded. Two buttons that aren't collinear, and a third one if there is
+// one, are solved for in closed form from the remaining distance to the prize. Any other buttons are branched on,
+// see `PressSearch::branch`.
+fn solve_machine(machine: &ClawMachine) -> Result<Vec<i128>, Infeasible> {
+    let movements = machine.buttons.iter().map(|button| button.movement).collect_vec();
+    if relaxed_cost(&machine.buttons, &(0..machine.buttons.len()).collect_vec(), machine.prize).is_none() {
+        return Err(Infeasible::OutsideCone);
+    }
+    if !in_lattice(&movements, machine.prize) {
+        return Err(Infeasible::OutsideLattice);
+    }
+
+    if let Some(presses) = search_presses(machine, true) {
+        return Ok(presses);
+    }
+    let has_limits = machine.total_limit.is_some() || machine.buttons.iter().any(|button| button.limit.is_some());
+    if has_limits && search_presses(machine, false).is_some() {
+        Err(Infeasible::OverLimits)
+    } else {
+        Err(Infeasible::NoIntegerSolution)
+    }
+}
+
+fn search_presses(machine: &ClawMachine, with_limits: bool) -> Option<Vec<i128>> {
+    let total_limit = machine.total_limit.filter(|_| with_limits);
+    let movements = machine.buttons.iter().map(|button| button.movement).collect_vec();
+    let bounds = machine.buttons.iter()
+        .map(|button| {
+            let (x, y) = button.movement;
+            let mut bound = button.limit.filter(|_| with_limits).unwrap_or(i128::MAX).min(total_limit.unwrap_or(i128::MAX));
+            if x > 0 {
+                bound = bound.min(machine.prize.0 / x);
+            }
+            if y > 0 {
+                bound = bound.min(machine.prize.1 / y);
+            }
+            if (x, y) == (0, 0) {
+                bound = 0;
+            }
+            bound
+        })
+        .collect_vec();
+
+    // Solving for the buttons with the most room saves the most branching, preferring a pair that isn't
+    // collinear so the solution for it is unique
+    let pairs = (0..machine.buttons.len()).tuple_combinations::<(usize, usize)>().collect_vec();
+    let mut solved = pairs.iter()
+        .max_by_key(|&&(i, j)| (cross(movements[i], movements[j]) != 0, bounds[i].min(bounds[j]), bounds[i].max(bounds[j])))
+        .map_or(vec![], |&(i, j)| vec![i, j]);
+    if solved.len() == 2 && cross(movements[solved[0]], movements[solved[1]]) != 0 {
+        let third = (0..machine.buttons.len()).filter(|i| !solved.contains(i)).max_by_key(|&i| bounds[i]);
+        solved.extend(third);
+    }
+
+    let mut order = (0..machine.buttons.len()).filter(|i| !solved.contains(i)).collect_vec();
+    order.sort_by_key(|&i| bounds[i]);
+    let mut search = PressSearch { machine, bounds, total_limit, solved, best: None };
+    search.branch(&order, &mut vec![0; machine.buttons.len()], machine.prize, 0, 0);
+    search.best.map(|(_, presses)| presses)
+}
+
+struct PressSearch<'a> {
+    machine: &'a ClawMachine,
+    bounds: Vec<i128>,
+    total_limit: Option<i128>,
+    // Buttons solved for at the end of every branch: none for a machine with a single button, a pair, or a pair
+    // that isn't collinear and one more
+    solved: Vec<usize>,
+    best: Option<(i128, Vec<i128>)>,
+}
+
+impl PressSearch<'_> {
+    // The cost with fractional presses of the buttons still to be decided is convex in the presses of this one,
+    // and lowest at its presses in the fractional optimum. Counts are tried going down from there and then up,
+    // stopping in each direction as soon as even fractional presses can't beat the best solution so far.
+    fn branch(&mut self, order: &[usize], presses: &mut Vec<i128>, remaining: Vector, cost: i128, pressed: i128) {
+        let Some((&button, rest)) = order.split_first() else {
+            self.finish(presses, remaining, cost, pressed);
+            return;
+        };
+
+        let (x, y) = self.machine.buttons[button].movement;
+        let mut highest = self.bounds[button].min(self.total_limit.map_or(i128::MAX, |limit| limit - pressed));
+        if x > 0 {
+            highest = highest.min(remaining.0 / x);
+        }
+        if y > 0 {
+            highest = highest.min(remaining.1 / y);
+        }
+        let usable = rest.iter().chain(&self.solved).copied().chain([button]).collect_vec();
+        let Some((_, optimum)) = relaxed_solution(&self.machine.buttons, &usable, remaining) else { return };
+        let start = optimum.iter()
+            .find(|&&(optimum_button, _)| optimum_button == button)
+            .map_or(0, |&(_, presses)| presses.floor() as i128)
+            .clamp(0, highest.max(0));
+
+        for count in (0..=start.min(highest)).rev() {
+            if !self.press(order, count, presses, (remaining, cost, pressed)) {
+                break;
+            }
+        }
+        for count in start + 1..=highest {
+            if !self.press(order, count, presses, (remaining, cost, pressed)) {
+                break;
+            }
+        }
+        presses[button] = 0;
+    }
+
+    // Branches on pressing the first button in the order count times, false if that can't beat the best solution
+    // so far
+    fn press(&mut self, order: &[usize], count: i128, presses: &mut Vec<i128>, (remaining, cost, pressed): (Vector, i128, i128)) -> bool {
+        let (&button, rest) = order.split_first().unwrap();
+        let unfixed = rest.iter().chain(&self.solved).copied().collect_vec();
+        let (x, y) = self.machine.buttons[button].movement;
+        let remaining = (remaining.0 - x * count, remaining.1 - y * count);
+        let cost = cost + self.machine.buttons[button].cost * count;
+        // Costs are whole numbers, so anything not at least one token cheaper than the best is no use
+        let promising = relaxed_cost(&self.machine.buttons, &unfixed, remaining)
+            .is_some_and(|lower_bound| self.best.as_ref().is_none_or(|(best, _)| (cost as f64 + lower_bound) < *best as f64 - 0.5));
+        if promising {
+            presses[button] = count;
+            self.branch(rest, presses, remaining, cost, pressed + count);
         }
+        promising
+    }
 
-        if min_token_cost < usize::MAX {
-            token_cost_total += min_token_cost;
+    fn finish(&mut self, presses: &[i128], remaining: Vector, cost: i128, pressed: i128) {
+        let total_left = self.total_limit.map(|limit| limit - pressed);
+        let buttons = &self.machine.buttons;
+        let bound = |button: usize| self.bounds[button];
+        let solution = match self.solved[..] {
+            [a, b, c] => solve_triple([&buttons[a], &buttons[b], &buttons[c]], [bound(a), bound(b), bound(c)], remaining, total_left)
+                .map(|counts| vec![(a, counts[0]), (b, counts[1]), (c, counts[2])]),
+            [a, b] => solve_pair(&buttons[a], &buttons[b], (bound(a), bound(b)), remaining, total_left)
+                .map(|(count_a, count_b)| vec![(a, count_a), (b, count_b)]),
+            _ => (remaining == (0, 0)).then(Vec::new),
+        };
+        let Some(solution) = solution else { return };
+
+        let mut presses = presses.to_vec();
+        for &(button, count) in &solution {
+            presses[button] = count;
+        }
+        let cost = cost + solution.iter().map(|&(button, count)| buttons[button].cost * count).sum::<i128>();
+        if self.best.as_ref().is_none_or(|(best, _)| cost < *best) {
+            self.best = Some((cost, presses));
         }
     }
+}
 
-    token_cost_total
+fn relaxed_cost(buttons: &[Button], usable: &[usize], target: Vector) -> Option<f64> {
+    relaxed_solution(buttons, usable, target).map(|(cost, _)| cost)
 }
 
-fn run_part_two(input_string: &str) -> i128 {
-    let re: Regex = Regex::new(r"(?ms)X[+=](\d+), Y[+=](\d+).*X[+=](\d+), Y[+=](\d+).*X[+=](\d+), Y[+=](\d+)").unwrap();
-    let mut input = Vec::new();
-
-    let mut blocks = input_string.split("\n\n");
-    while let Some(block) = blocks.next() {
-        let caps = re.captures(block).unwrap();
-        let a: (i128, i128) =  (caps[1].parse().unwrap(), caps[2].parse().unwrap());
-        let b: (i128, i128) = (caps[3].parse().unwrap(), caps[4].parse().unwrap());
-        let target = (
-            caps[5].parse::<i128>().unwrap() + 10000000000000,
-            caps[6].parse::<i128>().unwrap() + 10000000000000
-        );
-        input.push((a, b, target));
-    }
-
-    input.iter()
-        .flat_map(|inst| solve(*inst))
-        .map(|res| res.0 * 3 + res.1)
-        .sum()
+// Cheapest cost of reaching the target with fractional presses of the given buttons, ignoring limits, together
+// with the presses. In two dimensions that always takes at most two buttons, so trying every pair is enough.
+fn relaxed_solution(buttons: &[Button], usable: &[usize], target: Vector) -> Option<(f64, Vec<(usize, f64)>)> {
+    if target == (0, 0) {
+        return Some((0.0, vec![]));
+    }
+    let (target_x, target_y) = (target.0 as f64, target.1 as f64);
+    let mut cheapest: Option<(f64, Vec<(usize, f64)>)> = None;
+    let mut consider = |presses: Vec<(usize, f64)>| {
+        let cost = presses.iter().map(|&(button, count)| count * buttons[button].cost as f64).sum::<f64>();
+        if cheapest.as_ref().is_none_or(|(cheapest, _)| cost < *cheapest) {
+            cheapest = Some((cost, presses));
+        }
+    };
+
+    for &i in usable {
+        let (x, y) = buttons[i].movement;
+        if (x, y) != (0, 0) && cross((x, y), target) == 0 && x * target.0 + y * target.1 > 0 {
+            let presses = if x != 0 { target_x / x as f64 } else { target_y / y as f64 };
+            consider(vec![(i, presses)]);
+        }
+    }
+    for (&i, &j) in usable.iter().tuple_combinations() {
+        let ((a_x, a_y), (b_x, b_y)) = (buttons[i].movement, buttons[j].movement);
+        let determinant = (a_x * b_y - a_y * b_x) as f64;
+        if determinant == 0.0 {
+            continue;
+        }
+        let presses_a = (target_x * b_y as f64 - target_y * b_x as f64) / determinant;
+        let presses_b = (a_x as f64 * target_y - a_y as f64 * target_x) / determinant;
+        if presses_a >= 0.0 && presses_b >= 0.0 {
+            consider(vec![(i, presses_a), (j, presses_b)]);
+        }
+    }
+    cheapest
+}
+
+// Whether whole, possibly negative, numbers of presses can land on the target. The movements are brought down to
+// a basis (a, b), (0, d) of the positions they can reach, combining x components with the extended gcd.
+fn in_lattice(movements: &[Vector], target: Vector) -> bool {
+    let (mut a, mut b, mut d) = (0, 0, 0);
+    for &(x, y) in movements {
+        if x == 0 {
+            d = gcd_of(d, y);
+            continue;
+        }
+        let (g, s, t) = utils::extended_gcd(a, x);
+        let eliminated = (x / g) * b - (a / g) * y;
+        (a, b) = (g, s * b + t * y);
+        d = gcd_of(d, eliminated);
+    }
+
+    let (target_x, target_y) = target;
+    let rest_y = if a == 0 {
+        if target_x != 0 {
+            return false;
+        }
+        target_y
+    } else {
+        if target_x % a != 0 {
+            return false;
+        }
+        target_y - (target_x / a) * b
+    };
+    if d == 0 { rest_y == 0 } else { rest_y % d == 0 }
+}
+
+fn gcd_of(a: i128, b: i128) -> i128 {
+    utils::extended_gcd(a, b).0
 }
 
-fn solve(input: ((i128, i128), (i128, i128), (i128, i128))) -> Option<(i128, i128)> {
-    let a_x = input.0.0;
-    let b_x = input.1.0;
-    let target_x = input.2.0;
-    let a_y = input.0.1;
-    let b_y = input.1.1;
-    let target_y = input.2.1;
+// Cheapest presses of two buttons reaching the target within bounds on each and on their sum
+fn solve_pair(a: &Button, b: &Button, bounds: (i128, i128), target: Vector, total_left: Option<i128>) -> Option<(i128, i128)> {
+    let (a_x, a_y) = a.movement;
+    let (b_x, b_y) = b.movement;
+    let (target_x, target_y) = target;
+    let within_limits = |(x, y): (i128, i128)| {
+        x >= 0 && y >= 0 && x <= bounds.0 && y <= bounds.1 && total_left.is_none_or(|total| x + y <= total)
+    };
 
     // Solving system like
     // a_x * X + b_x * Y = target_x
@@ -85,23 +363,171 @@ fn solve(input: ((i128, i128), (i128, i128), (i128, i128))) -> Option<(i128, i12
 
     // a_y * b_x * Y - a_x * b_y * Y = a_y * target_x - a_x * target_y
     // Y = (a_y * target_x - a_x * target_y) / (a_y * b_x - a_x * b_y )
-    // X = (target_x - b_x * Y) / a_x
+    // X = (target_x * b_y - target_y * b_x) / (a_x * b_y - a_y * b_x)
+
+    let determinant = a_y * b_x - a_x * b_y;
+    if determinant == 0 {
+        return solve_collinear(a, b, bounds, target, total_left);
+    }
+
+    let y_numerator = a_y * target_x - a_x * target_y;
+    let x_numerator = target_y * b_x - target_x * b_y;
+    if y_numerator % determinant != 0 || x_numerator % determinant != 0 {
+        return None;
+    }
+    Some((x_numerator / determinant, y_numerator / determinant)).filter(|&presses| within_limits(presses))
+}
 
-    let y = (a_y * target_x - a_x * target_y) % (a_y * b_x - a_x * b_y );
-    let y2 = (a_y * target_x - a_x * target_y) / (a_y * b_x - a_x * b_y );
-    let x = (target_x - b_x * y2) % a_x;
-    let x2 = (target_x - b_x * y2) / a_x;
+// Both buttons move along the same line, so the target has to be on it as well and there may be many ways to
+// get there. Along one axis that's a * i + b * j = target, solved with the extended gcd as
+// i = i0 + (b / g) * t, j = j0 - (a / g) * t for any t keeping both counts within their bounds. The cost changes
+// linearly with t, so the cheapest solution is at one end of that range.
+fn solve_collinear(button_a: &Button, button_b: &Button, bounds: (i128, i128), target: Vector, total_left: Option<i128>) -> Option<(i128, i128)> {
+    if cross(button_a.movement, target) != 0 || cross(button_b.movement, target) != 0 {
+        return None;
+    }
 
-    if y != 0 || x != 0 {
-        None
+    // Any axis the buttons move along works, the other one then follows
+    let (a, b, target) = if button_a.movement.0 != 0 || button_b.movement.0 != 0 {
+        (button_a.movement.0, button_b.movement.0, target.0)
+    } else if button_a.movement.1 != 0 || button_b.movement.1 != 0 {
+        (button_a.movement.1, button_b.movement.1, target.1)
     } else {
-        Some((x2, y2))
+        // Neither button moves the claw
+        return (target == (0, 0)).then_some((0, 0));
+    };
+
+    // A button that doesn't move the claw is never worth pressing
+    let within = |presses: i128, bound: i128| presses >= 0 && presses <= bound && total_left.is_none_or(|total| presses <= total);
+    if a == 0 {
+        return (target % b == 0 && within(target / b, bounds.1)).then(|| (0, target / b));
+    }
+    if b == 0 {
+        return (target % a == 0 && within(target / a, bounds.0)).then(|| (target / a, 0));
+    }
+
+    let (g, x, y) = utils::extended_gcd(a, b);
+    if target % g != 0 {
+        return None;
     }
+    let (i0, j0) = (x * (target / g), y * (target / g));
+    let (i_step, j_step) = (b / g, -a / g);
+
+    let t = cheapest_step(&[
+        (i0, i_step, bounds.0),
+        (j0, j_step, bounds.1),
+        (i0 + j0, i_step + j_step, total_left.unwrap_or(i128::MAX)),
+    ], button_a.cost * i_step + button_b.cost * j_step)?;
+    Some((i0 + i_step * t, j0 + j_step * t))
+}
+
+// Presses of three buttons, the first two not collinear. For every presses k of the third, the first two follow
+// from Cramer's rule, and are whole numbers for k in one residue class modulo some m. So the solutions are
+// k = k0 + m * t with the other two changing linearly along with t, just like in `solve_collinear`.
+fn solve_triple(buttons: [&Button; 3], bounds: [i128; 3], target: Vector, total_left: Option<i128>) -> Option<[i128; 3]> {
+    let [a, b, c] = buttons.map(|button| button.movement);
+    let determinant = cross(a, b);
+    // i * determinant = cross(target, b) - k * cross(c, b), j * determinant = cross(a, target) - k * cross(a, c)
+    let (i_start, i_per_press) = (cross(target, b), -cross(c, b));
+    let (j_start, j_per_press) = (cross(a, target), -cross(a, c));
+    let (k0, m) = utils::linear_congruence(i_per_press, -i_start, determinant)
+        .zip(utils::linear_congruence(j_per_press, -j_start, determinant))
+        .and_then(|(first, second)| utils::chinese_remainder(first, second))?;
+
+    let (i0, i_step) = ((i_start + i_per_press * k0) / determinant, i_per_press * m / determinant);
+    let (j0, j_step) = ((j_start + j_per_press * k0) / determinant, j_per_press * m / determinant);
+    let t = cheapest_step(&[
+        (i0, i_step, bounds[0]),
+        (j0, j_step, bounds[1]),
+        (k0, m, bounds[2]),
+        (i0 + j0 + k0, i_step + j_step + m, total_left.unwrap_or(i128::MAX)),
+    ], buttons[0].cost * i_step + buttons[1].cost * j_step + buttons[2].cost * m)?;
+    Some([i0 + i_step * t, j0 + j_step * t, k0 + m * t])
+}
+
+// Given values start + step * t that each have to stay between 0 and an upper bound, the t for which the cost
+// is lowest. The cost changes linearly with t, so that is at one end of the range.
+fn cheapest_step(constraints: &[(i128, i128, i128)], cost_per_step: i128) -> Option<i128> {
+    let mut lowest = i128::MIN;
+    let mut highest = i128::MAX;
+    for &(start, step, upper) in constraints {
+        if step == 0 {
+            if start < 0 || start > upper {
+                return None;
+            }
+            continue;
+        }
+        let (from, to) = if step > 0 {
+            (div_ceil(-start, step), div_floor(upper.saturating_sub(start), step))
+        } else {
+            (div_ceil(upper.saturating_sub(start), step), div_floor(-start, step))
+        };
+        lowest = lowest.max(from);
+        highest = highest.min(to);
+    }
+    if lowest > highest {
+        return None;
+    }
+    Some(if cost_per_step > 0 { lowest } else { highest })
+}
+
+fn div_floor(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    if numerator % denominator != 0 && (numerator < 0) != (denominator < 0) { quotient - 1 } else { quotient }
+}
+
+fn div_ceil(numerator: i128, denominator: i128) -> i128 {
+    -div_floor(-numerator, denominator)
+}
+
+fn print_solutions(input_string: &str, args: &[String]) {
+    let mut machines = parse_claw_machines(input_string, 10000000000000);
+    let mut explain = false;
+    for arg in args {
+        if let Some(costs) = arg.strip_prefix("costs=") {
+            let costs = costs.split(',').map(|cost| cost.parse::<i128>().unwrap()).collect_vec();
+            assert!(costs.iter().all(|&cost| cost >= 0), "Costs can't be negative");
+            for machine in &mut machines {
+                for (button, &cost) in machine.buttons.iter_mut().zip(&costs) {
+                    button.cost = cost;
+                }
+            }
+        } else if let Some(limit) = arg.strip_prefix("limit=") {
+            let limit = limit.parse().expect("Limit has to be a number");
+            for machine in &mut machines {
+                machine.total_limit = Some(limit);
+            }
+        } else if arg == "explain" {
+            explain = true;
+        } else {
+            panic!("Unknown option {}", arg);
+        }
+    }
+
+    let mut total = 0;
+    for (index, machine) in machines.iter().enumerate() {
+        match solve_machine(machine) {
+            Ok(presses) => {
+                let cost = token_cost(machine, &presses);
+                total += cost;
+                if explain {
+                    let described = machine.buttons.iter().zip(&presses)
+                        .map(|(button, presses)| format!("{} x{}", button.name, presses))
+                        .join(", ");
+                    println!("machine {}: {} for {} tokens", index + 1, described, cost);
+                }
+            }
+            Err(reason) if explain => println!("machine {}: {}", index + 1, reason),
+            Err(_) => {}
+        }
+    }
+    println!("{}", total);
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day13::{run_part_one, run_part_two};
+    use crate::day::day13::{parse_claw_machines, run_part_one, run_part_two, solve_machine, token_cost, Button, ClawMachine, Infeasible};
+    use itertools::Itertools;
     use crate::day::utils;
 
     fn example_input() -> String {
@@ -142,4 +568,164 @@ Prize: X=18641, Y=10279")
     fn test_input_part_two() {
         assert_eq!(run_part_two(&utils::read_input(13)), 79352015273424);
     }
+
+    fn two_buttons(a: (i128, i128), b: (i128, i128), prize: (i128, i128), costs: (i128, i128)) -> ClawMachine {
+        let button = |name: &str, movement, cost| Button { name: name.to_string(), movement, cost, limit: None };
+        ClawMachine { buttons: vec![button("A", a, costs.0), button("B", b, costs.1)], prize, total_limit: None }
+    }
+
+    fn solve(machine: &ClawMachine) -> Option<(i128, i128)> {
+        solve_machine(machine).ok().map(|presses| (presses[0], presses[1]))
+    }
+
+    #[test]
+    fn test_solve_rejects_negative_presses() {
+        // 3 * -1 + 4 = 1, -1 + 2 * 4 = 7
+        assert_eq!(solve(&two_buttons((3, 1), (1, 2), (1, 7), (3, 1))), None);
+        assert_eq!(solve(&two_buttons((3, 1), (1, 2), (7, 9), (3, 1))), Some((1, 4)));
+    }
+
+    #[test]
+    fn test_solve_collinear_buttons() {
+        let machine = |prize| two_buttons((2, 2), (3, 3), prize, (3, 1));
+        // (6, 0), (3, 2) and (0, 4) all reach the prize
+        assert_eq!(solve(&machine((12, 12))), Some((0, 4)));
+        assert_eq!(solve(&two_buttons((2, 2), (3, 3), (12, 12), (1, 3))), Some((6, 0)));
+        assert_eq!(solve(&machine((7, 7))), Some((2, 1)));
+        assert_eq!(solve(&machine((1, 1))), None);
+        // Not on the line the buttons move along
+        assert_eq!(solve(&machine((12, 13))), None);
+        // Only even positions can be reached
+        assert_eq!(solve(&two_buttons((2, 4), (4, 8), (7, 14), (3, 1))), None);
+        // Buttons moving along y only, one of them not at all
+        assert_eq!(solve(&two_buttons((0, 0), (0, 5), (0, 25), (3, 1))), Some((0, 5)));
+    }
+
+    #[test]
+    fn test_extended_gcd() {
+        for (a, b) in [(240, 46), (-240, 46), (3, 7), (10, 5), (0, 9)] {
+            let (g, x, y) = utils::extended_gcd(a, b);
+            assert!(g > 0);
+            assert_eq!(a * x + b * y, g);
+            assert_eq!(a % g, 0);
+            assert_eq!(b % g, 0);
+        }
+    }
+
+    #[test]
+    fn test_more_buttons_and_limits() {
+        let machines = parse_claw_machines("\
+Button A: X+94, Y+34
+Button B: X+22, Y+67
+Button C: X+1, Y+1, cost 0
+Prize: X=8400, Y=5400
+
+Button A: X+94, Y+34, limit 50
+Button B: X+22, Y+67
+Prize: X=8400, Y=5400
+
+Button A: X+2, Y+0, cost 1
+Button B: X+0, Y+2, cost 1
+Button C: X+3, Y+3, cost 5, limit 2
+Prize: X=7, Y=7
+Limit: 4", 0);
+        // Going by the difference between X and Y, 60a - 45b = 3000 so a = 50 + 3k and b = 4k, with the free
+        // diagonal moves making up the rest
+        let presses = solve_machine(&machines[0]).unwrap();
+        assert_eq!(presses, vec![50, 0, 3700]);
+        assert_eq!(token_cost(&machines[0], &presses), 150);
+        assert_eq!(machines[1].buttons[0].limit, Some(50));
+        assert_eq!(solve_machine(&machines[1]), Err(Infeasible::OverLimits));
+        // Only odd positions need C, and one press of it leaves 4, 4 for two presses each of A and B
+        assert_eq!(solve_machine(&machines[2]), Err(Infeasible::OverLimits));
+        let unlimited = ClawMachine { total_limit: None, ..machines[2].clone() };
+        assert_eq!(solve_machine(&unlimited), Ok(vec![2, 2, 1]));
+    }
+
+    #[test]
+    fn test_infeasibility_reasons() {
+        assert_eq!(solve_machine(&two_buttons((1, 2), (2, 1), (10, 1), (3, 1))), Err(Infeasible::OutsideCone));
+        assert_eq!(solve_machine(&two_buttons((2, 0), (0, 2), (3, 4), (3, 1))), Err(Infeasible::OutsideLattice));
+        assert_eq!(solve_machine(&two_buttons((1, 0), (0, 1), (3, 4), (3, 1))), Ok(vec![3, 4]));
+        // 2 * -1 + 3 * 1 = 1
+        assert_eq!(solve_machine(&two_buttons((2, 2), (3, 3), (1, 1), (3, 1))), Err(Infeasible::NoIntegerSolution));
+        let mut limited = two_buttons((2, 2), (3, 3), (1, 1), (3, 1));
+        limited.total_limit = Some(10);
+        assert_eq!(solve_machine(&limited), Err(Infeasible::NoIntegerSolution));
+    }
+
+    fn lands_on_prize(machine: &ClawMachine, presses: &[i128]) -> bool {
+        let position = machine.buttons.iter().zip(presses)
+            .fold((0, 0), |(x, y), (button, &count)| (x + button.movement.0 * count, y + button.movement.1 * count));
+        presses.iter().all(|&count| count >= 0) && position == machine.prize
+    }
+
+    #[test]
+    fn test_extra_buttons_far_away_prizes() {
+        let machines = parse_claw_machines("\
+Button A: X+94, Y+34
+Button B: X+22, Y+67
+Button C: X+31, Y+29
+Prize: X=8400, Y=5400
+
+Button A: X+94, Y+34
+Button B: X+22, Y+67
+Button C: X+31, Y+29
+Button D: X+5, Y+3, cost 2
+Prize: X=8400, Y=5400", 10000000000000);
+        let three = solve_machine(&machines[0]).unwrap();
+        assert!(lands_on_prize(&machines[0], &three));
+        let four = solve_machine(&machines[1]).unwrap();
+        assert!(lands_on_prize(&machines[1], &four));
+        // An extra button can only make it cheaper
+        assert!(token_cost(&machines[1], &four) <= token_cost(&machines[0], &three));
+    }
+
+    #[test]
+    fn test_three_buttons_against_brute_force() {
+        let mut state: u64 = 3;
+        let mut next = |modulus: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) % modulus) as i128
+        };
+        for _ in 0..200 {
+            let buttons = (0..3)
+                .map(|index| Button { name: index.to_string(), movement: (next(6) + 1, next(6) + 1), cost: next(4), limit: Some(next(12)) })
+                .collect_vec();
+            let machine = ClawMachine { buttons, prize: (next(40), next(40)), total_limit: Some(next(30)) };
+            let cheapest = (0..3).map(|index| 0..=machine.buttons[index].limit.unwrap()).multi_cartesian_product()
+                .filter(|presses| lands_on_prize(&machine, presses) && presses.iter().sum::<i128>() <= machine.total_limit.unwrap())
+                .map(|presses| token_cost(&machine, &presses))
+                .min();
+            let solution = solve_machine(&machine);
+            assert_eq!(solution.as_ref().ok().map(|presses| token_cost(&machine, presses)), cheapest, "{:?}", machine);
+            if let Ok(presses) = solution {
+                assert!(lands_on_prize(&machine, &presses));
+            }
+        }
+    }
+
+    #[test]
+    fn test_many_buttons_against_brute_force() {
+        let buttons = [(3, 1, 4), (1, 3, 2), (2, 2, 3), (5, 1, 6)];
+        let machine = ClawMachine {
+            buttons: buttons.iter().enumerate()
+                .map(|(index, &(x, y, cost))| Button { name: index.to_string(), movement: (x, y), cost, limit: Some(8) })
+                .collect(),
+            prize: (29, 19),
+            total_limit: Some(12),
+        };
+        let mut cheapest = None;
+        for presses in (0..4).map(|_| 0..=8).multi_cartesian_product() {
+            let position = presses.iter().zip(&buttons).fold((0, 0), |(x, y), (&count, &(dx, dy, _))| (x + count * dx, y + count * dy));
+            if position == machine.prize && presses.iter().sum::<i128>() <= 12 {
+                let cost = token_cost(&machine, &presses);
+                if cheapest.is_none_or(|cheapest| cost < cheapest) {
+                    cheapest = Some(cost);
+                }
+            }
+        }
+        let presses = solve_machine(&machine).unwrap();
+        assert_eq!(Some(token_cost(&machine, &presses)), cheapest);
+    }
 }
@@ -2,12 +2,70 @@ use crate::day::utils;
 use glam::IVec2;
 use itertools::Itertools;
 use regex::Regex;
-use std::cmp::max;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 pub fn run() {
     let input_string = utils::read_input(14);
     println!("{}", run_part_one(&input_string, IVec2::new(101, 103)));
     println!("{}", run_part_two(&input_string, IVec2::new(101, 103)));
+
+    // `cargo run -- 14 0..10403` looks at all seconds in the range, `size=11,7` sets other bathroom dimensions.
+    // `frames=directory` writes a PBM image per second instead (PGM with `pgm`, every nth second with `step=n`),
+    // `sheet=file.pgm` one image of the `tiles=n` frames in which the robots bunch up the most.
+    let args = std::env::args().skip(2).collect_vec();
+    if !args.is_empty() {
+        let mut dimensions = IVec2::new(101, 103);
+        let mut times = 0..100;
+        let mut step = 1;
+        let mut format = ImageFormat::Pbm;
+        let mut tiles = 36;
+        let mut frames_directory = None;
+        let mut sheet_file = None;
+        for arg in &args {
+            if let Some(size) = arg.strip_prefix("size=") {
+                let (x, y) = size.split(',').map(|value| value.parse().unwrap()).collect_tuple().expect("Size has to be given as x,y");
+                dimensions = IVec2::new(x, y);
+            } else if let Some(value) = arg.strip_prefix("step=") {
+                step = value.parse().expect("Step has to be a number");
+                if step == 0 {
+                    panic!("Step has to be at least 1");
+                }
+            } else if let Some(value) = arg.strip_prefix("tiles=") {
+                tiles = value.parse().expect("Tiles have to be a number");
+            } else if arg == "pgm" {
+                format = ImageFormat::Pgm;
+            } else if let Some(directory) = arg.strip_prefix("frames=") {
+                frames_directory = Some(directory.to_string());
+            } else if let Some(file) = arg.strip_prefix("sheet=") {
+                sheet_file = Some(file.to_string());
+            } else if let Some((start, end)) = arg.split_once("..").filter(|_| !arg.contains('=')) {
+                times = start.parse().unwrap()..end.parse().unwrap();
+            } else {
+                panic!("Unknown option {}", arg);
+            }
+        }
+
+        if frames_directory.is_none() && sheet_file.is_none() {
+            print_analysis(&input_string, dimensions, times);
+            return;
+        }
+
+        let robots = parse_robots(&input_string);
+        let times = times.step_by(step).collect_vec();
+        if let Some(directory) = frames_directory {
+            let files = export_frames(&robots, dimensions, &times, Path::new(&directory), format).expect("Failed to write frames");
+            println!("wrote {} frames to {}", files.len(), directory);
+        }
+        if let Some(file) = sheet_file {
+            let (sheet, ranking) = contact_sheet(&robots, dimensions, &times, tiles);
+            std::fs::write(&file, sheet).expect("Failed to write contact sheet");
+            for (tile, (time, score)) in ranking.iter().enumerate() {
+                println!("tile {}: {} seconds, spread {}", tile + 1, time, score);
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -16,129 +74,269 @@ struct Robot {
     velocity: IVec2,
 }
 
+impl Robot {
+    // Where the robot is after `time` seconds, wrapping around the bathroom walls. Going back in time works too.
+    fn position_at(&self, time: i64, bathroom_dimensions: IVec2) -> IVec2 {
+        let axis = |position: i32, velocity: i32, size: i32| {
+            // Only the time within one period matters, which keeps the product small
+            (position as i64 + velocity as i64 * time.rem_euclid(size as i64)).rem_euclid(size as i64) as i32
+        };
+        IVec2::new(
+            axis(self.position.x, self.velocity.x, bathroom_dimensions.x),
+            axis(self.position.y, self.velocity.y, bathroom_dimensions.y),
+        )
+    }
+}
+
 fn run_part_one(input_string: &str, bathroom_dimensions: IVec2) -> usize {
-    let re: Regex = Regex::new(r"p=(\d+),(\d+) v=(-?\d+),(-?\d+)").unwrap();
+    safety_factor(&parse_robots(input_string), bathroom_dimensions, 100)
+}
 
-    let mut input: Vec<Robot> = Vec::new();
-
-    input_string.lines().for_each(|line| {
-        let caps = re.captures(line).unwrap();
-        let position = IVec2::new(caps[1].parse().unwrap(), caps[2].parse().unwrap());
-        let velocity = IVec2::new(caps[3].parse().unwrap(), caps[4].parse().unwrap());
-        let robot = Robot { position, velocity };
-        input.push(robot);
-    });
-
-    for _ in 0..100 {
-        input.iter_mut().for_each(|robot| {
-            let mut new_pos = robot.position + robot.velocity;
-            if new_pos.x < 0 {
-                new_pos.x = bathroom_dimensions.x + new_pos.x;
-            }
-            if new_pos.y < 0 {
-                new_pos.y = bathroom_dimensions.y + new_pos.y;
-            }
+// Product of the number of robots in each quadrant, robots on the middle lines don't count
+fn safety_factor(robots: &[Robot], bathroom_dimensions: IVec2, time: i64) -> usize {
+    let mid_x = bathroom_dimensions.x / 2;
+    let mid_y = bathroom_dimensions.y / 2;
 
-            robot.position = IVec2::new(new_pos.x % (bathroom_dimensions.x), new_pos.y %
-                (bathroom_dimensions.y))
-        })
+    let mut quadrants = [0; 4];
+    for robot in robots {
+        let position = robot.position_at(time, bathroom_dimensions);
+        if position.x == mid_x || position.y == mid_y {
+            continue;
+        }
+        quadrants[(position.x > mid_x) as usize * 2 + (position.y > mid_y) as usize] += 1;
     }
 
-    let mid_x = bathroom_dimensions.x / 2;
-    let mid_y = bathroom_dimensions.y / 2;
+    quadrants.iter().product()
+}
 
-    let mut q_1 = 0;
-    let mut q_2 = 0;
-    let mut q_3 = 0;
-    let mut q_4 = 0;
-
-    input.iter()
-        .for_each(|robot| {
-            if robot.position.x < mid_x && robot.position.y < mid_y {
-                q_1 += 1;
-            } else if robot.position.x < mid_x && robot.position.y > mid_y {
-                q_2 += 1;
-            } else if robot.position.x > mid_x && robot.position.y < mid_y {
-                q_3 += 1;
-            } else if robot.position.x > mid_x && robot.position.y > mid_y {
-                q_4 += 1;
-            } else {
-            }
-        });
+fn min_safety_factor(robots: &[Robot], bathroom_dimensions: IVec2, times: Range<i64>) -> Option<(i64, usize)> {
+    times.map(|time| (time, safety_factor(robots, bathroom_dimensions, time)))
+        .min_by_key(|&(_, factor)| factor)
+}
+
+// How many seconds each cell had a robot on it, summed over all robots
+fn occupancy_heatmap(robots: &[Robot], bathroom_dimensions: IVec2, times: Range<i64>) -> Vec<Vec<usize>> {
+    let mut heatmap = vec![vec![0; bathroom_dimensions.x as usize]; bathroom_dimensions.y as usize];
+    for time in times {
+        for robot in robots {
+            let position = robot.position_at(time, bathroom_dimensions);
+            heatmap[position.y as usize][position.x as usize] += 1;
+        }
+    }
+    heatmap
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Collision {
+    time: i64,
+    robots: (usize, usize),
+    position: IVec2,
+}
+
+// Two robots meet whenever the gap between them is a multiple of the room size on both axes. On each axis that's
+// a linear congruence in the time, and the two combine like in `find_easter_egg`. Returns the first time at or
+// after `from` and how often it repeats.
+fn meeting_times(first: &Robot, second: &Robot, bathroom_dimensions: IVec2, from: i64) -> Option<(i64, i64)> {
+    // closing_speed * t = -gap (mod size) on each axis
+    let axis = |gap: i32, closing_speed: i32, size: i32| utils::linear_congruence(closing_speed as i64, -gap as i64, size as i64);
+    let gap = first.position - second.position;
+    let closing_speed = first.velocity - second.velocity;
+    let x = axis(gap.x, closing_speed.x, bathroom_dimensions.x)?;
+    let y = axis(gap.y, closing_speed.y, bathroom_dimensions.y)?;
 
-    q_1 * q_2 * q_3 * q_4
+    let (time, period) = utils::chinese_remainder(x, y)?;
+    let periods_to_skip = ((from - time).max(0) + period - 1) / period;
+    Some((time + periods_to_skip * period, period))
 }
 
-fn run_part_two(input_string: &str, bathroom_dimensions: IVec2) -> i128 {
+fn first_collision(robots: &[Robot], bathroom_dimensions: IVec2, times: Range<i64>) -> Option<Collision> {
+    robots.iter().enumerate().tuple_combinations()
+        .filter_map(|((i, first), (j, second))| {
+            let (time, _) = meeting_times(first, second, bathroom_dimensions, times.start)?;
+            Some(Collision { time, robots: (i, j), position: first.position_at(time, bathroom_dimensions) })
+        })
+        .filter(|collision| times.contains(&collision.time))
+        .min_by_key(|collision| (collision.time, collision.robots))
+}
+
+fn print_analysis(input_string: &str, bathroom_dimensions: IVec2, times: Range<i64>) {
+    let robots = parse_robots(input_string);
+    if let Some((time, factor)) = min_safety_factor(&robots, bathroom_dimensions, times.clone()) {
+        println!("lowest safety factor {} after {} seconds", factor, time);
+    }
+    match first_collision(&robots, bathroom_dimensions, times.clone()) {
+        Some(collision) => println!("robots {} and {} meet at {},{} after {} seconds",
+                                    collision.robots.0, collision.robots.1, collision.position.x, collision.position.y, collision.time),
+        None => println!("no robots meet"),
+    }
+    let heatmap = occupancy_heatmap(&robots, bathroom_dimensions, times);
+    let (hottest_y, hottest_x, visits) = heatmap.iter().enumerate()
+        .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, &visits)| (y, x, visits)))
+        .max_by_key(|&(_, _, visits)| visits)
+        .unwrap();
+    println!("busiest cell {},{} with {} robot seconds", hottest_x, hottest_y, visits);
+}
+
+fn parse_robots(input_string: &str) -> Vec<Robot> {
     let re: Regex = Regex::new(r"p=(\d+),(\d+) v=(-?\d+),(-?\d+)").unwrap();
+    input_string.lines()
+        .map(|line| {
+            let caps = re.captures(line).unwrap();
+            let position = IVec2::new(caps[1].parse().unwrap(), caps[2].parse().unwrap());
+            let velocity = IVec2::new(caps[3].parse().unwrap(), caps[4].parse().unwrap());
+            Robot { position, velocity }
+        })
+        .collect()
+}
 
-    let mut input: Vec<Robot> = Vec::new();
-
-    input_string.lines().for_each(|line| {
-        let caps = re.captures(line).unwrap();
-        let position = IVec2::new(caps[1].parse().unwrap(), caps[2].parse().unwrap());
-        let velocity = IVec2::new(caps[3].parse().unwrap(), caps[4].parse().unwrap());
-        let robot = Robot { position, velocity };
-        input.push(robot);
-    });
-
-    for iteration in 0.. {
-        input.iter_mut().for_each(|robot| {
-            let mut new_pos = robot.position + robot.velocity;
-            if new_pos.x < 0 {
-                new_pos.x = bathroom_dimensions.x + new_pos.x;
-            }
-            if new_pos.y < 0 {
-                new_pos.y = bathroom_dimensions.y + new_pos.y;
-            }
+fn run_part_two(input_string: &str, bathroom_dimensions: IVec2) -> i64 {
+    find_easter_egg(&parse_robots(input_string), bathroom_dimensions).expect("No time fits both axes")
+}
 
-            robot.position = IVec2::new(new_pos.x % (bathroom_dimensions.x), new_pos.y %
-                (bathroom_dimensions.y))
-        });
+// The robots draw the tree when they bunch up, so their positions spread the least then. Every robot is back
+// in the same column after `width` seconds and in the same row after `height` seconds, so each axis only needs
+// checking over one period. The two times are then put together with the chinese remainder theorem.
+fn find_easter_egg(robots: &[Robot], bathroom_dimensions: IVec2) -> Option<i64> {
+    let tightest = |size: i32, axis: fn(IVec2) -> i32| {
+        (0..size as i64)
+            .min_by_key(|&time| {
+                let coordinates = robots.iter()
+                    .map(|robot| axis(robot.position_at(time, bathroom_dimensions)) as i64);
+                scaled_variance(coordinates)
+            })
+            .unwrap()
+    };
+    let time_x = tightest(bathroom_dimensions.x, |vector| vector.x);
+    let time_y = tightest(bathroom_dimensions.y, |vector| vector.y);
 
-        // if there is a pattern, assume that it'll have a bunch of elements new to each other.
+    utils::chinese_remainder((time_x, bathroom_dimensions.x as i64), (time_y, bathroom_dimensions.y as i64)).map(|(time, _)| time)
+}
 
-        let mut possible_candidate = false;
+// Variance times the number of values squared, which stays a whole number
+fn scaled_variance(values: impl Iterator<Item = i64>) -> i64 {
+    let (count, sum, sum_of_squares) = values.fold((0, 0, 0), |(count, sum, squares), value| (count + 1, sum + value, squares + value * value));
+    count * sum_of_squares - sum * sum
+}
 
-        let mut x_coords = input.iter().map(|robot| robot.position.x).collect_vec();
-        x_coords.sort();
-        let mut max_chain = 0;
-        let mut chain_len = 0;
-        for i in 1..x_coords.len() {
-            if x_coords[i - 1].abs_diff(x_coords[i]) == 1 {
-                chain_len += 1;
-            } else {
-                max_chain = max(max_chain, chain_len);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageFormat {
+    // Black and white, a robot or not
+    Pbm,
+    // Greyscale, darker the more robots share a cell
+    Pgm,
+}
+
+impl ImageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Pgm => "pgm",
+        }
+    }
+}
+
+// Plain (ASCII) netpbm formats, which keep lines under 70 characters
+const NETPBM_LINE_LENGTH: usize = 70;
+
+fn netpbm_pixels(rows: &[Vec<usize>]) -> String {
+    let mut pixels = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for value in row {
+            let value = value.to_string();
+            if !line.is_empty() && line.len() + 1 + value.len() > NETPBM_LINE_LENGTH {
+                pixels += &line;
+                pixels.push('\n');
+                line.clear();
             }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &value;
         }
+        pixels += &line;
+        pixels.push('\n');
+    }
+    pixels
+}
 
-        if chain_len < 70 && chain_len > 10 {
-            possible_candidate = true;
+// Number of robots on every cell after `time` seconds
+fn occupancy_frame(robots: &[Robot], bathroom_dimensions: IVec2, time: i64) -> Vec<Vec<usize>> {
+    occupancy_heatmap(robots, bathroom_dimensions, time..time + 1)
+}
+
+fn render_frame(frame: &[Vec<usize>], format: ImageFormat) -> String {
+    let (width, height) = (frame[0].len(), frame.len());
+    match format {
+        // 1 is black in PBM
+        ImageFormat::Pbm => {
+            let bits = frame.iter().map(|row| row.iter().map(|&count| (count > 0) as usize).collect_vec()).collect_vec();
+            format!("P1\n{} {}\n{}", width, height, netpbm_pixels(&bits))
         }
+        // 0 is black in PGM, so the busiest cells come out black and empty ones white
+        ImageFormat::Pgm => {
+            let max_count = frame.iter().flatten().copied().max().unwrap_or(0).max(1);
+            let shades = frame.iter().map(|row| row.iter().map(|&count| max_count - count).collect_vec()).collect_vec();
+            format!("P2\n{} {}\n{}\n{}", width, height, max_count, netpbm_pixels(&shades))
+        }
+    }
+}
+
+fn export_frames(robots: &[Robot], bathroom_dimensions: IVec2, times: &[i64], directory: &Path, format: ImageFormat) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(directory)?;
+    times.iter()
+        .map(|&time| {
+            let file = directory.join(format!("frame_{:05}.{}", time, format.extension()));
+            std::fs::write(&file, render_frame(&occupancy_frame(robots, bathroom_dimensions, time), format))?;
+            Ok(file)
+        })
+        .collect()
+}
+
+// How spread out the robots are, lower meaning more bunched up. Same measure `find_easter_egg` goes by.
+fn clustering_score(robots: &[Robot], bathroom_dimensions: IVec2, time: i64) -> i64 {
+    let positions = robots.iter().map(|robot| robot.position_at(time, bathroom_dimensions)).collect_vec();
+    scaled_variance(positions.iter().map(|position| position.x as i64))
+        + scaled_variance(positions.iter().map(|position| position.y as i64))
+}
 
+// Tiles the frames with the lowest clustering scores into one greyscale image, tightest first, reading left to
+// right and top to bottom, with grey lines between them. Also returns time and score for each tile.
+fn contact_sheet(robots: &[Robot], bathroom_dimensions: IVec2, times: &[i64], tiles: usize) -> (String, Vec<(i64, i64)>) {
+    let ranking = times.iter()
+        .map(|&time| (time, clustering_score(robots, bathroom_dimensions, time)))
+        .sorted_by_key(|&(time, score)| (score, time))
+        .take(tiles)
+        .collect_vec();
 
-        if possible_candidate {
-            for i in 0..bathroom_dimensions.y {
-                for j in 0..bathroom_dimensions.x {
-                    let has_robot = input.iter().any(|robot| { robot.position.x == j && robot.position.y == i });
-                    if has_robot {
-                        print!("\x1b[42m  \x1b[0m");
-                    } else {
-                        print!("\x1b[41m  \x1b[0m");
-                    }
-                }
-                println!("");
+    const BLACK: usize = 0;
+    const GREY: usize = 1;
+    const WHITE: usize = 2;
+    let columns = (ranking.len() as f64).sqrt().ceil().max(1.0) as usize;
+    let rows = ranking.len().div_ceil(columns).max(1);
+    let (tile_width, tile_height) = (bathroom_dimensions.x as usize, bathroom_dimensions.y as usize);
+    let width = columns * (tile_width + 1) - 1;
+    let height = rows * (tile_height + 1) - 1;
+
+    let mut pixels = vec![vec![GREY; width]; height];
+    for (tile, &(time, _)) in ranking.iter().enumerate() {
+        let (left, top) = ((tile % columns) * (tile_width + 1), (tile / columns) * (tile_height + 1));
+        let frame = occupancy_frame(robots, bathroom_dimensions, time);
+        for (y, row) in frame.iter().enumerate() {
+            for (x, &count) in row.iter().enumerate() {
+                pixels[top + y][left + x] = if count > 0 { BLACK } else { WHITE };
             }
-            println!("Iteration: {}", iteration);
         }
     }
 
-    6243
+    (format!("P2\n{} {}\n{}\n{}", width, height, WHITE, netpbm_pixels(&pixels)), ranking)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day::day14::run_part_one;
+    use crate::day::day14::{contact_sheet, export_frames, find_easter_egg, first_collision, min_safety_factor, occupancy_heatmap, parse_robots, render_frame, run_part_one, run_part_two, safety_factor, ImageFormat, Robot};
+    use std::collections::HashSet;
+    use std::path::PathBuf;
     use crate::day::utils;
     use glam::IVec2;
 
@@ -168,4 +366,133 @@ p=9,5 v=-3,-3")
         assert_eq!(run_part_one(&utils::read_input(14), IVec2::new(101, 103)), 222901875);
     }
 
+    #[test]
+    fn test_input_part_two() {
+        assert_eq!(run_part_two(&utils::read_input(14), IVec2::new(101, 103)), 6243);
+    }
+
+    #[test]
+    fn test_finds_planted_cluster() {
+        assert_eq!(find_easter_egg(&planted_cluster(), IVec2::new(11, 7)), Some(40));
+    }
+
+    // Robots that all meet around the middle of an 11x7 room at second 40, coming from all over
+    fn planted_cluster() -> Vec<Robot> {
+        (0..30)
+            .map(|index| {
+                let velocity = IVec2::new(index % 7 - 3, index % 5 - 2);
+                let target = IVec2::new(5 + index % 2, 3 + index % 3 - 1);
+                let position = target - velocity * 40;
+                Robot { position: IVec2::new(position.x.rem_euclid(11), position.y.rem_euclid(7)), velocity }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_render_frame() {
+        let frame = vec![vec![0, 2, 0], vec![1, 0, 0]];
+        assert_eq!(render_frame(&frame, ImageFormat::Pbm), "P1\n3 2\n0 1 0\n1 0 0\n");
+        assert_eq!(render_frame(&frame, ImageFormat::Pgm), "P2\n3 2\n2\n2 0 2\n1 2 2\n");
+
+        // Plain netpbm lines stay under 70 characters
+        let wide = render_frame(&[vec![1; 101]], ImageFormat::Pbm);
+        assert!(wide.lines().all(|line| line.len() <= 70));
+        assert_eq!(wide.lines().skip(2).flat_map(|line| line.split(' ')).count(), 101);
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let robots = planted_cluster();
+        let times = (0..77).step_by(2).collect::<Vec<_>>();
+        let (sheet, ranking) = contact_sheet(&robots, IVec2::new(11, 7), &times, 9);
+        assert_eq!(ranking.len(), 9);
+        assert_eq!(ranking[0].0, 40);
+        assert!(ranking.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+
+        // 3x3 tiles of 11x7 with a pixel between them
+        let mut lines = sheet.lines();
+        assert_eq!(lines.next(), Some("P2"));
+        assert_eq!(lines.next(), Some("35 23"));
+        assert_eq!(lines.next(), Some("2"));
+        let pixels = lines.flat_map(|line| line.split(' ')).collect::<Vec<_>>();
+        assert_eq!(pixels.len(), 35 * 23);
+        // The separator column after the first tile is grey
+        assert_eq!(pixels[11], "1");
+    }
+
+    #[test]
+    fn test_export_frames() {
+        // Removes the frames again even when an assertion fails
+        struct TempDirectory(PathBuf);
+        impl Drop for TempDirectory {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_dir_all(&self.0);
+            }
+        }
+
+        let robots = parse_robots(&example_input());
+        let directory = TempDirectory(std::env::temp_dir().join(format!("day14_frames_{}", std::process::id())));
+        let files = export_frames(&robots, IVec2::new(11, 7), &[0, 5, 10], &directory.0, ImageFormat::Pgm).unwrap();
+        assert_eq!(files, vec![directory.0.join("frame_00000.pgm"), directory.0.join("frame_00005.pgm"), directory.0.join("frame_00010.pgm")]);
+        let first = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(first.starts_with("P2\n11 7\n"));
+    }
+
+    #[test]
+    fn test_chinese_remainder() {
+        assert_eq!(utils::chinese_remainder((2, 3), (3, 5)), Some((8, 15)));
+        assert_eq!(utils::chinese_remainder((0, 101), (0, 103)), Some((0, 10403)));
+        // Sharing a factor of 2, the remainders have to agree on parity
+        assert_eq!(utils::chinese_remainder((1, 4), (3, 6)), Some((9, 12)));
+        assert_eq!(utils::chinese_remainder((1, 4), (2, 6)), None);
+    }
+
+    #[test]
+    fn test_position_at() {
+        let robot = Robot { position: IVec2::new(2, 4), velocity: IVec2::new(2, -3) };
+        let dimensions = IVec2::new(11, 7);
+        let path = [(2, 4), (4, 1), (6, 5), (8, 2), (10, 6), (1, 3)];
+        for (time, &(x, y)) in path.iter().enumerate() {
+            assert_eq!(robot.position_at(time as i64, dimensions), IVec2::new(x, y));
+        }
+        assert_eq!(robot.position_at(-1, dimensions), IVec2::new(0, 0));
+        assert_eq!(robot.position_at(5 + 77 * 1_000_000_000_000, dimensions), IVec2::new(1, 3));
+    }
+
+    #[test]
+    fn test_safety_factor_over_time() {
+        let robots = parse_robots(&example_input());
+        let dimensions = IVec2::new(11, 7);
+        assert_eq!(safety_factor(&robots, dimensions, 100), 12);
+        // Nobody starts out in the top right quadrant, and the first of the minima is picked
+        assert_eq!(min_safety_factor(&robots, dimensions, 0..77), Some((0, 0)));
+        assert_eq!(min_safety_factor(&robots, dimensions, 1..5), Some((2, 9)));
+        assert_eq!(min_safety_factor(&robots, dimensions, 6..11), Some((7, 4)));
+        assert_eq!(min_safety_factor(&robots, dimensions, 6..6), None);
+
+        let heatmap = occupancy_heatmap(&robots, dimensions, 0..10);
+        assert_eq!(heatmap.iter().flatten().sum::<usize>(), 12 * 10);
+        assert_eq!(heatmap[3][10], 5);
+        assert_eq!(heatmap[5][9], 5);
+        assert_eq!(heatmap[5][6], 5);
+        assert_eq!(heatmap[0][0], 2);
+        assert_eq!(heatmap.iter().flatten().max(), Some(&5));
+    }
+
+    #[test]
+    fn test_first_collision_matches_simulation() {
+        let robots = parse_robots(&example_input());
+        let dimensions = IVec2::new(11, 7);
+        for start in [0, 1, 5, 30] {
+            let expected_time = (start..start + 77).find(|&time| {
+                let positions = robots.iter().map(|robot| robot.position_at(time, dimensions)).collect::<HashSet<_>>();
+                positions.len() < robots.len()
+            });
+            let collision = first_collision(&robots, dimensions, start..start + 77).unwrap();
+            assert_eq!(Some(collision.time), expected_time);
+            let (first, second) = collision.robots;
+            assert_eq!(robots[first].position_at(collision.time, dimensions), robots[second].position_at(collision.time, dimensions));
+        }
+        assert_eq!(first_collision(&robots[..1], dimensions, 0..1000), None);
+    }
 }
@@ -1,4 +1,5 @@
 use std::fs;
+use num_traits::{Euclid, PrimInt, Signed};
 
 pub fn read_input(day: u8) -> String {
     let file_name = format!("input/day{:02}.txt", day);
@@ -18,4 +19,37 @@ pub fn input_into_matrix(input_string: &str) -> Vec<Vec<char>> {
     input_string.lines()
         .for_each(|line| matrix.push(line.chars().into_iter().collect()));
     matrix
-}
\ No newline at end of file
+}
+
+// Returns (g, x, y) with a * x + b * y = g = gcd(a, b) and g not negative
+pub fn extended_gcd<T: PrimInt + Signed>(a: T, b: T) -> (T, T, T) {
+    if b.is_zero() {
+        return if a.is_negative() { (-a, -T::one(), T::zero()) } else { (a, T::one(), T::zero()) };
+    }
+    let (g, x, y) = extended_gcd(b, a % b);
+    (g, y, x - (a / b) * y)
+}
+
+// Every t with coefficient * t = value (mod modulus), as the smallest non-negative one and how often it repeats
+pub fn linear_congruence<T: PrimInt + Signed + Euclid>(coefficient: T, value: T, modulus: T) -> Option<(T, T)> {
+    let modulus = modulus.abs();
+    let (g, inverse, _) = extended_gcd(coefficient.rem_euclid(&modulus), modulus);
+    if !value.rem_euclid(&g).is_zero() {
+        return None;
+    }
+    let period = modulus / g;
+    Some(((value / g).rem_euclid(&period) * inverse.rem_euclid(&period) % period, period))
+}
+
+// Every t with t = a (mod m) and t = b (mod n), if the two agree where m and n share factors. Returned like
+// `linear_congruence`, repeating every lcm(m, n).
+pub fn chinese_remainder<T: PrimInt + Signed + Euclid>((a, m): (T, T), (b, n): (T, T)) -> Option<(T, T)> {
+    let (g, p, _) = extended_gcd(m, n);
+    if !((b - a) % g).is_zero() {
+        return None;
+    }
+    let lcm = m / g * n;
+    // m * p = g (mod n), so stepping a by multiples of m covers the difference to b
+    let steps = ((b - a) / g).rem_euclid(&(n / g)) * p.rem_euclid(&(n / g)) % (n / g);
+    Some(((a + m * steps).rem_euclid(&lcm), lcm))
+}